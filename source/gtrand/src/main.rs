@@ -370,7 +370,8 @@ impl ManagedScheme for RandScheme {
     }
 
     fn shutdown(&mut self) -> bool {
-        return false;
+        // nothing to clean up
+        return true;
     }
 }
 
@@ -385,6 +386,7 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
 
     libredox::call::setrens(0, 0).expect("randd: failed to enter null namespace");
     let _ = scheme.message("starting!");
+    let _ = scheme.set_ready();
     while let Some(request) = socket
         .next_request(SignalBehavior::Restart)
         .expect("error reading packet")
@@ -396,6 +398,9 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
             socket
             .write_responses(&[response], SignalBehavior::Restart)
             .expect("error writing packet");
+        if scheme.should_exit() {
+            break;
+        }
     }
 
     process::exit(0);
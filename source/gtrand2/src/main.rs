@@ -400,7 +400,8 @@ impl ManagedScheme for RandScheme {
     }
 
     fn shutdown(&mut self) -> bool {
-        return false;
+        // nothing to clean up
+        return true;
     }
 }
 
@@ -428,6 +429,9 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
             socket
             .write_responses(&[response], SignalBehavior::Restart)
             .expect("error writing packet");
+        if scheme.should_exit() {
+            break;
+        }
     }
 
     process::exit(0);
@@ -1,11 +1,11 @@
 use redox_scheme::{CallerCtx, OpenResult, Scheme};
 use syscall::{
-    Error, Result, EBADF,
+    Error, Result, EBADF, EOVERFLOW,
 };
 
 use chrono::Local;
 use hashbrown::HashMap;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::atomic::Ordering;
 use std::sync::*;
 use zerocopy::IntoBytes;
@@ -24,6 +24,10 @@ struct RequestsScheme {
 }
 struct TimeStampScheme(i64);
 struct MessageScheme([u8; 40]);
+// holds the state exported by the main scheme's checkpoint(), see BaseScheme::read
+struct StateScheme(Vec<u8>);
+// set by the daemon once it can serve requests, see BaseScheme::set_ready
+struct ReadyScheme(bool);
 // will hold a command enum?
 struct ControlScheme {
     stop: bool,
@@ -37,11 +41,15 @@ pub struct BaseScheme {
     time_stamp_scheme: ManagementSubScheme,
     message_scheme: ManagementSubScheme,
     control_scheme: ManagementSubScheme,
+    state_scheme: ManagementSubScheme,
+    ready_scheme: ManagementSubScheme,
     // handlers holds a map of the file descriptors/id to
     // the actual scheme object
     handlers: HashMap<usize, ManagementSubScheme>,
     next_mgmt_id: AtomicUsize,
     management: Arc<Mutex<Management>>,
+    // set once a stop has been requested and the main scheme's shutdown() has finished
+    exit_ready: AtomicBool,
 }
 
 impl BaseScheme {
@@ -68,12 +76,30 @@ impl BaseScheme {
                 stop: false,
                 clear: false,
             }))),
+            state_scheme: Arc::new(Mutex::new(Box::new(StateScheme(Vec::new())))),
+            ready_scheme: Arc::new(Mutex::new(Box::new(ReadyScheme(false)))),
             handlers: HashMap::new(),
             next_mgmt_id: 9999.into(),
             management: Arc::new(Mutex::new(Management::new())),
+            exit_ready: AtomicBool::new(false),
         }
     }
 
+    /// Returns true once the service monitor has asked this service to stop and the main scheme's
+    /// [ManagedScheme::shutdown] has finished. Daemons should check this after handling each request
+    /// and exit when it returns true.
+    pub fn should_exit(&self) -> bool {
+        let _ = self.update();
+        self.exit_ready.load(Ordering::Relaxed)
+    }
+
+    /// Tells the service monitor this service is ready to serve requests. Only needed for services whose
+    /// registry entry has `ready_check = "ready"`; until this is called, their dependents are not started.
+    pub fn set_ready(&self) -> Result<()> {
+        let mut ready_lock = self.ready_scheme.lock().map_err(|_err| Error::new(EBADF))?;
+        ready_lock.write(0, b"ready", 0, 0).map(|_| ())
+    }
+
     fn handler(&self, id: usize) -> Result<SubSchemeGuard> {
         let _update = self.update()?;
         match self.handlers.get(&id) {
@@ -83,7 +109,7 @@ impl BaseScheme {
     }
 
     // need to consider what value will be returned based on what update was made?
-    // for now return 1 if cleared and 0 if not, error if control scheme cannot be locked
+    // for now return 1 if cleared, 2 if stopping and 0 otherwise, error if control scheme cannot be locked
     fn update(&self) -> Result<usize> {
         let mut control_lock = self
             .control_scheme
@@ -117,8 +143,14 @@ impl BaseScheme {
             let _ = self.message("message cleared");
             return Ok(1);
         } else if r_buf[0] == 1 {
-            // graceful shutdown code could go here?
-            Ok(0)
+            // the service monitor asked us to stop, let the main scheme clean up until it is ready to exit
+            if !self.exit_ready.load(Ordering::Relaxed) {
+                let mut main_lock = self.main_scheme.lock().map_err(|_err| Error::new(EBADF))?;
+                if main_lock.shutdown() {
+                    self.exit_ready.store(true, Ordering::Relaxed);
+                }
+            }
+            Ok(2)
         } else {
             // this is a normal data update.
             let mut requests_lock = self
@@ -141,6 +173,12 @@ impl BaseScheme {
         }
     }
 
+    fn is_state_scheme(&self, id: usize) -> bool {
+        self.handlers
+            .get(&id)
+            .is_some_and(|scheme| Arc::ptr_eq(scheme, &self.state_scheme))
+    }
+
     pub fn message(&self, message: &str) -> Result<[u8; 40]> {
         let msg_arr: &mut [u8] = &mut [0; 40];
         if message.len() > 32 {
@@ -220,6 +258,18 @@ impl Scheme for BaseScheme {
                     Ok(new_id)
                 }
 
+                b"state" => {
+                    let new_id = self.next_mgmt_id.fetch_sub(1, Ordering::Relaxed);
+                    self.handlers.insert(new_id, self.state_scheme.clone());
+                    Ok(new_id)
+                }
+
+                b"ready" => {
+                    let new_id = self.next_mgmt_id.fetch_sub(1, Ordering::Relaxed);
+                    self.handlers.insert(new_id, self.ready_scheme.clone());
+                    Ok(new_id)
+                }
+
                 // if there is nothing on the buffer then assume we want the main scheme
                 b"" => {
                     let main_dup = self
@@ -263,6 +313,11 @@ impl Scheme for BaseScheme {
     }
 
     fn read(&mut self, id: usize, buf: &mut [u8], _offset: u64, _flags: u32) -> Result<usize> {
+        // reading the state subscheme takes a fresh checkpoint of the main scheme
+        if self.is_state_scheme(id) {
+            let state = self.main_scheme.lock().map_err(|_err| Error::new(EBADF))?.checkpoint();
+            self.state_scheme.lock().map_err(|_err| Error::new(EBADF))?.write(id, &state, 0, 0)?;
+        }
         // lock the subscheme and management struct
         let mut subscheme: SubSchemeGuard = self.handler(id)?;
         let mut management = self.management.lock().map_err(|_err| Error::new(EBADF))?;
@@ -287,6 +342,12 @@ impl Scheme for BaseScheme {
         } else if subscheme.count_ops() {
             management.errors += 1;
         }
+        drop(subscheme);
+        drop(management);
+        // writing the state subscheme hands a previous checkpoint back to the main scheme
+        if result.is_ok() && self.is_state_scheme(id) {
+            self.main_scheme.lock().map_err(|_err| Error::new(EBADF))?.restore(buffer);
+        }
         return result;
     }
 
@@ -441,6 +502,38 @@ impl Scheme for MessageScheme {
     }
 }
 
+impl ManagedScheme for StateScheme {}
+impl Scheme for StateScheme {
+    fn read(&mut self, _id: usize, buf: &mut [u8], _offset: u64, _flags: u32) -> Result<usize> {
+        // unlike the other subschemes, the state can be any length, and a truncated checkpoint is no use to restore
+        if buf.len() < self.0.len() {
+            return Err(Error::new(EOVERFLOW));
+        }
+        buf[..self.0.len()].copy_from_slice(&self.0);
+        Ok(self.0.len())
+    }
+
+    fn write(&mut self, _id: usize, buf: &[u8], _offset: u64, _flags: u32) -> Result<usize> {
+        self.0 = buf.to_vec();
+        Ok(buf.len())
+    }
+}
+
+impl ManagedScheme for ReadyScheme {}
+impl Scheme for ReadyScheme {
+    fn read(&mut self, _id: usize, buf: &mut [u8], _offset: u64, _flags: u32) -> Result<usize> {
+        fill_buffer(buf, &[u8::from(self.0)]);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, _id: usize, buf: &[u8], _offset: u64, _flags: u32) -> Result<usize> {
+        if buf == b"ready" {
+            self.0 = true;
+        }
+        Ok(buf.len())
+    }
+}
+
 impl ManagedScheme for ControlScheme {}
 impl Scheme for ControlScheme {
     fn read(&mut self, _id: usize, buf: &mut [u8], _offset: u64, _flags: u32) -> Result<usize> {
@@ -530,6 +623,15 @@ pub trait ManagedScheme: Scheme {
     fn count_ops(&self) -> bool {
         return false;
     }
+    /// Exports the state the service needs to pick up where it left off after a restart.
+    /// The service monitor reads this through the "state" subscheme before a `services restart --restore`.
+    fn checkpoint(&mut self) -> Vec<u8> {
+        return Vec::new();
+    }
+    /// Receives the state exported by [ManagedScheme::checkpoint] before the service was last restarted.
+    fn restore(&mut self, _state: &[u8]) {}
+    /// Called when the service monitor asks the service to stop. Returns true once the scheme has cleaned up
+    /// and the daemon can exit, otherwise it is called again on the next request. See [BaseScheme::should_exit].
     fn shutdown(&mut self) -> bool {
         return false;
     }
@@ -0,0 +1,415 @@
+use hashbrown::{HashMap, HashSet};
+
+use crate::registry::{ServiceEntry, Target};
+
+/// The order services should be started in at boot, computed from their dependencies.
+pub struct BootPlan {
+    /// The names of services that can be started, ordered so that every service comes after all of its dependencies.
+    pub order: Vec<String>,
+    /// The names of services that can never be started, along with the reason why.
+    pub rejected: Vec<(String, String)>,
+}
+
+/// Builds a dependency graph from the `depends` list of every service and sorts it topologically.
+///
+/// Services that depend on a service missing from `services`, that are part of a dependency cycle,
+/// or that depend on a rejected service are left out of the order and listed in [BootPlan::rejected] instead.
+/// Services with no ordering constraint between them are sorted by name so that boot order is stable.
+pub fn boot_plan(services: &HashMap<String, ServiceEntry>) -> BootPlan {
+    let mut rejected: Vec<(String, String)> = Vec::new();
+    let mut rejected_names: HashSet<String> = HashSet::new();
+
+    // reject services with unknown dependencies first
+    let mut names: Vec<&String> = services.keys().collect();
+    names.sort();
+    for name in &names {
+        let depends = &services[*name].config.depends;
+        if let Some(missing) = depends.iter().find(|dep| !services.contains_key(*dep)) {
+            rejected.push((name.to_string(), format!("depends on unknown service '{}'", missing)));
+            rejected_names.insert(name.to_string());
+        }
+    }
+
+    // then anything that (indirectly) depends on a rejected service
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for name in &names {
+            if rejected_names.contains(*name) {
+                continue;
+            }
+            let depends = &services[*name].config.depends;
+            if let Some(dep) = depends.iter().find(|dep| rejected_names.contains(*dep)) {
+                rejected.push((name.to_string(), format!("depends on '{}' which cannot be started", dep)));
+                rejected_names.insert(name.to_string());
+                changed = true;
+            }
+        }
+    }
+
+    // Kahn's algorithm over the remaining services
+    let mut remaining: HashMap<&String, usize> = HashMap::new();
+    let mut dependents: HashMap<&String, Vec<&String>> = HashMap::new();
+    for name in names.iter().filter(|name| !rejected_names.contains(**name)) {
+        let depends = &services[*name].config.depends;
+        let mut unique: Vec<&String> = depends.iter().collect();
+        unique.sort();
+        unique.dedup();
+        remaining.insert(*name, unique.len());
+        for dep in unique {
+            dependents.entry(dep).or_default().push(*name);
+        }
+    }
+
+    let mut ready: Vec<&String> = remaining
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(name, _)| *name)
+        .collect();
+    let mut order: Vec<String> = Vec::new();
+    while !ready.is_empty() {
+        // pop the alphabetically first service that is ready
+        ready.sort_by(|a, b| b.cmp(a));
+        let name = ready.pop().unwrap();
+        remaining.remove(name);
+        order.push(name.clone());
+        if let Some(children) = dependents.get(name) {
+            for child in children {
+                if let Some(count) = remaining.get_mut(*child) {
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.push(*child);
+                    }
+                }
+            }
+        }
+    }
+
+    // anything left over is either in a cycle or depends on one
+    let mut leftover: Vec<&String> = remaining.keys().copied().collect();
+    leftover.sort();
+    for name in leftover {
+        let cycle = find_cycle(services, name, &remaining);
+        let reason = if cycle.contains(name) {
+            format!("part of dependency cycle {}", cycle.join(" -> "))
+        } else {
+            format!("depends on dependency cycle {}", cycle.join(" -> "))
+        };
+        rejected.push((name.clone(), reason));
+    }
+
+    BootPlan { order, rejected }
+}
+
+/// Returns `name` and every service it depends on, directly or indirectly, ordered so that
+/// each service comes after all of its dependencies. `name` is always last.
+///
+/// Returns an [Err] describing the problem if a dependency is unknown or part of a cycle.
+pub fn dependency_chain(services: &HashMap<String, ServiceEntry>, name: &str) -> Result<Vec<String>, String> {
+    let mut chain: Vec<String> = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    visit(services, name, &mut path, &mut chain)?;
+    Ok(chain)
+}
+
+/// Depth-first post-order visit used by [dependency_chain]. `path` holds the services currently being visited.
+fn visit(services: &HashMap<String, ServiceEntry>, name: &str, path: &mut Vec<String>, chain: &mut Vec<String>) -> Result<(), String> {
+    if chain.iter().any(|done| done == name) {
+        return Ok(());
+    }
+    if let Some(pos) = path.iter().position(|visiting| visiting == name) {
+        let mut cycle = path[pos..].to_vec();
+        cycle.push(name.to_string());
+        return Err(format!("dependency cycle {}", cycle.join(" -> ")));
+    }
+    let Some(service) = services.get(name) else {
+        return Err(format!("unknown service '{}'", name));
+    };
+
+    path.push(name.to_string());
+    for dep in &service.config.depends {
+        visit(services, dep, path, chain)?;
+    }
+    path.pop();
+    chain.push(name.to_string());
+    Ok(())
+}
+
+/// Returns `name` and every service that depends on it, directly or indirectly, ordered so that
+/// each service comes before all of its dependencies (reverse topological order). `name` is always last.
+pub fn stop_order(services: &HashMap<String, ServiceEntry>, name: &str) -> Vec<String> {
+    let mut order: Vec<String> = Vec::new();
+    let mut visited: HashSet<String> = HashSet::new();
+    visit_dependents(services, name, &mut visited, &mut order);
+    order
+}
+
+/// Depth-first post-order visit over reverse dependency edges used by [stop_order].
+fn visit_dependents(services: &HashMap<String, ServiceEntry>, name: &str, visited: &mut HashSet<String>, order: &mut Vec<String>) {
+    if !visited.insert(name.to_string()) {
+        return;
+    }
+    let mut dependents: Vec<&String> = services
+        .values()
+        .filter(|service| service.config.depends.iter().any(|dep| dep == name))
+        .map(|service| &service.config.name)
+        .collect();
+    dependents.sort();
+    for dependent in dependents {
+        visit_dependents(services, dependent, visited, order);
+    }
+    order.push(name.to_string());
+}
+
+/// Returns the services in the target `name` and in every target it includes, sorted by name.
+///
+/// Returns an [Err] describing the problem if a target or service is unknown, or if targets include each other.
+pub fn target_services(services: &HashMap<String, ServiceEntry>, targets: &HashMap<String, Target>, name: &str) -> Result<Vec<String>, String> {
+    let mut members: Vec<String> = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    visit_target(services, targets, name, &mut path, &mut members)?;
+    members.sort();
+    members.dedup();
+    Ok(members)
+}
+
+/// Depth-first visit used by [target_services]. `path` holds the targets currently being visited.
+fn visit_target(
+    services: &HashMap<String, ServiceEntry>,
+    targets: &HashMap<String, Target>,
+    name: &str,
+    path: &mut Vec<String>,
+    members: &mut Vec<String>,
+) -> Result<(), String> {
+    if let Some(pos) = path.iter().position(|visiting| visiting == name) {
+        let mut cycle: Vec<String> = path[pos..].iter().map(|target| format!("@{}", target)).collect();
+        cycle.push(format!("@{}", name));
+        return Err(format!("target cycle {}", cycle.join(" -> ")));
+    }
+    let Some(target) = targets.get(name) else {
+        return Err(format!("unknown target '@{}'", name));
+    };
+
+    path.push(name.to_string());
+    for member in &target.members {
+        if let Some(included) = member.strip_prefix('@') {
+            visit_target(services, targets, included, path, members)?;
+        } else if services.contains_key(member) {
+            members.push(member.clone());
+        } else {
+            return Err(format!("target '@{}' contains unknown service '{}'", name, member));
+        }
+    }
+    path.pop();
+    Ok(())
+}
+
+/// Returns the services in `members` and every service they depend on, directly or indirectly, ordered so that
+/// each service comes after all of its dependencies.
+///
+/// Returns an [Err] describing the problem if a dependency is unknown or part of a cycle.
+pub fn group_start_order(services: &HashMap<String, ServiceEntry>, members: &[String]) -> Result<Vec<String>, String> {
+    let mut order: Vec<String> = Vec::new();
+    for member in members {
+        // each chain is already in dependency order, so keeping the first appearance of each service keeps it so
+        for name in dependency_chain(services, member)? {
+            if !order.contains(&name) {
+                order.push(name);
+            }
+        }
+    }
+    Ok(order)
+}
+
+/// Returns true if every dependency of `name` is running, so it can be started. A dependency that was launched
+/// but has not passed its `ready_check` yet is still starting, so `name` keeps waiting for it.
+pub fn dependencies_ready(services: &HashMap<String, ServiceEntry>, name: &str) -> bool {
+    services[name]
+        .config
+        .depends
+        .iter()
+        .all(|dep| services.get(dep).is_some_and(|dep| dep.is_running()))
+}
+
+/// Returns the dependency that keeps `name` from ever being started by a group start, along with why: either it
+/// `failed` to start, or it is not running and is neither `starting` nor `pending`, so nothing will start it.
+pub fn start_blocked_by(
+    services: &HashMap<String, ServiceEntry>,
+    name: &str,
+    starting: &HashSet<String>,
+    pending: &[String],
+    failed: &HashSet<String>,
+) -> Option<(String, &'static str)> {
+    services[name].config.depends.iter().find_map(|dep| {
+        if failed.contains(dep) {
+            Some((dep.clone(), "failed to start"))
+        } else if !starting.contains(dep) && !pending.contains(dep) && !services.get(dep).is_some_and(|dep| dep.is_running()) {
+            Some((dep.clone(), "is not running"))
+        } else {
+            None
+        }
+    })
+}
+
+/// Follows unresolved dependencies from `start` until a service repeats, and returns the cycle that was found
+/// with its first service repeated at the end (e.g. `["a", "b", "a"]`).
+fn find_cycle(services: &HashMap<String, ServiceEntry>, start: &String, unresolved: &HashMap<&String, usize>) -> Vec<String> {
+    let mut path: Vec<String> = Vec::new();
+    let mut current = start.clone();
+    loop {
+        if let Some(pos) = path.iter().position(|name| *name == current) {
+            let mut cycle = path.split_off(pos);
+            cycle.push(current);
+            return cycle;
+        }
+        path.push(current.clone());
+        // every unresolved service has at least one unresolved dependency
+        let mut depends: Vec<&String> = services[&current]
+            .config
+            .depends
+            .iter()
+            .filter(|dep| unresolved.contains_key(*dep))
+            .collect();
+        depends.sort();
+        match depends.first() {
+            Some(dep) => current = (*dep).clone(),
+            None => return path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Service;
+    use shared::ServiceState;
+
+    fn services(list: &[(&str, &[&str])]) -> HashMap<String, ServiceEntry> {
+        let mut map = HashMap::new();
+        for (name, depends) in list {
+            map.insert(
+                name.to_string(),
+                ServiceEntry::new(Service {
+                    name: name.to_string(),
+                    r#type: "daemon".to_string(),
+                    depends: depends.iter().map(|dep| dep.to_string()).collect(),
+                    ..Default::default()
+                }),
+            );
+        }
+        map
+    }
+
+    fn set_state(services: &mut HashMap<String, ServiceEntry>, name: &str, state: ServiceState) {
+        services.get_mut(name).unwrap().state = state;
+    }
+
+    fn targets(list: &[(&str, &[&str])]) -> HashMap<String, Target> {
+        list.iter()
+            .map(|(name, members)| {
+                let target = Target {
+                    name: name.to_string(),
+                    members: members.iter().map(|member| member.to_string()).collect(),
+                };
+                (name.to_string(), target)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn targets_include_other_targets() {
+        let services = services(&[("a", &[]), ("b", &["a"]), ("c", &[])]);
+        let targets = targets(&[("boot", &["c", "@storage"]), ("storage", &["b"])]);
+        let members = target_services(&services, &targets, "boot").unwrap();
+        assert_eq!(members, vec!["b", "c"]);
+        assert_eq!(group_start_order(&services, &members).unwrap(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn target_cycles_are_rejected() {
+        let services = services(&[("a", &[])]);
+        let targets = targets(&[("x", &["a", "@y"]), ("y", &["@x"])]);
+        assert_eq!(target_services(&services, &targets, "x"), Err(String::from("target cycle @x -> @y -> @x")));
+        assert!(target_services(&services, &targets, "z").is_err());
+    }
+
+    #[test]
+    fn dependencies_start_first() {
+        let plan = boot_plan(&services(&[("c", &["b"]), ("b", &["a"]), ("a", &[]), ("d", &[])]));
+        assert_eq!(plan.order, vec!["a", "b", "c", "d"]);
+        assert!(plan.rejected.is_empty());
+    }
+
+    #[test]
+    fn unknown_dependencies_are_rejected() {
+        let plan = boot_plan(&services(&[("a", &["missing"]), ("b", &["a"]), ("c", &[])]));
+        assert_eq!(plan.order, vec!["c"]);
+        let rejected: Vec<&str> = plan.rejected.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(rejected, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn chain_lists_dependencies_first() {
+        let map = services(&[("c", &["b", "a"]), ("b", &["a"]), ("a", &[]), ("d", &[])]);
+        assert_eq!(dependency_chain(&map, "c").unwrap(), vec!["a", "b", "c"]);
+        let map = services(&[("a", &["b"]), ("b", &["a"])]);
+        assert_eq!(dependency_chain(&map, "a").unwrap_err(), "dependency cycle a -> b -> a");
+    }
+
+    #[test]
+    fn dependents_stop_first() {
+        let map = services(&[("a", &[]), ("b", &["a"]), ("c", &["a"]), ("d", &["b", "c"]), ("e", &[])]);
+        assert_eq!(stop_order(&map, "a"), vec!["d", "b", "c", "a"]);
+        assert_eq!(stop_order(&map, "e"), vec!["e"]);
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let plan = boot_plan(&services(&[("a", &["b"]), ("b", &["a"]), ("c", &["a"]), ("d", &[])]));
+        assert_eq!(plan.order, vec!["d"]);
+        assert_eq!(plan.rejected.len(), 3);
+        assert_eq!(plan.rejected[0], ("a".to_string(), "part of dependency cycle a -> b -> a".to_string()));
+        assert_eq!(plan.rejected[2], ("c".to_string(), "depends on dependency cycle a -> b -> a".to_string()));
+    }
+
+    #[test]
+    fn dependents_wait_until_dependencies_are_ready() {
+        let mut services = services(&[("a", &[]), ("b", &[]), ("c", &["a", "b"])]);
+        assert!(dependencies_ready(&services, "a"));
+        assert!(!dependencies_ready(&services, "c"));
+        set_state(&mut services, "a", ServiceState::Running);
+        set_state(&mut services, "b", ServiceState::Starting);
+        assert!(!dependencies_ready(&services, "c"));
+        set_state(&mut services, "b", ServiceState::Restarted);
+        assert!(dependencies_ready(&services, "c"));
+    }
+
+    #[test]
+    fn unknown_dependencies_are_never_ready() {
+        let services = services(&[("a", &["missing"])]);
+        assert!(!dependencies_ready(&services, "a"));
+    }
+
+    #[test]
+    fn failed_dependencies_block_their_dependents() {
+        let services = services(&[("a", &[]), ("b", &["a"]), ("c", &["b"])]);
+        let pending = vec!["b".to_string(), "c".to_string()];
+        let mut failed = HashSet::new();
+        assert_eq!(start_blocked_by(&services, "b", &HashSet::new(), &pending, &failed), Some(("a".to_string(), "is not running")));
+        failed.insert("a".to_string());
+        assert_eq!(start_blocked_by(&services, "b", &HashSet::new(), &pending, &failed), Some(("a".to_string(), "failed to start")));
+        // "b" is skipped as failed too, so the block reaches "c"
+        failed.insert("b".to_string());
+        assert_eq!(start_blocked_by(&services, "c", &HashSet::new(), &pending[1..], &failed), Some(("b".to_string(), "failed to start")));
+    }
+
+    #[test]
+    fn dependencies_still_to_start_do_not_block() {
+        let mut services = services(&[("a", &[]), ("b", &[]), ("c", &["a", "b"])]);
+        let starting = HashSet::from(["a".to_string()]);
+        let pending = vec!["b".to_string(), "c".to_string()];
+        assert_eq!(start_blocked_by(&services, "c", &starting, &pending, &HashSet::new()), None);
+        set_state(&mut services, "b", ServiceState::Running);
+        assert_eq!(start_blocked_by(&services, "c", &starting, &pending[1..], &HashSet::new()), None);
+    }
+}
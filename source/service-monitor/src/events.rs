@@ -0,0 +1,159 @@
+use hashbrown::HashMap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use shared::ServiceEvent;
+use std::{collections::VecDeque, fs};
+
+use crate::registry::{MonitorConfig, ServiceEntry};
+
+/// Where the event journal is saved if `persist_events` is set.
+const EVENTS_PATH: &str = "/usr/share/smevents.toml";
+
+/// The contents of the events file.
+#[derive(Serialize, Deserialize, Default)]
+struct EventsFile {
+    #[serde(default)]
+    event: Vec<ServiceEvent>,
+}
+
+/// A ring buffer of the most recent lifecycle events of every service, oldest first.
+///
+/// Services record their events as they happen, and the journal takes them in with [EventJournal::collect].
+pub struct EventJournal {
+    events: VecDeque<ServiceEvent>,
+    capacity: usize,
+    persist: bool,
+}
+
+impl EventJournal {
+    /// Creates an empty journal, or reloads the saved one if `persist_events` is set.
+    pub fn new(config: &MonitorConfig) -> EventJournal {
+        let mut journal = EventJournal {
+            events: VecDeque::new(),
+            capacity: config.event_capacity,
+            persist: config.persist_events,
+        };
+        if journal.persist {
+            match fs::read_to_string(EVENTS_PATH) {
+                Ok(saved) => match toml::from_str::<EventsFile>(&saved) {
+                    Ok(file) => journal.push_all(file.event),
+                    Err(err) => warn!("ignoring unreadable events file {}: {}", EVENTS_PATH, err),
+                },
+                Err(err) => info!("no saved events loaded from {}: {}", EVENTS_PATH, err),
+            }
+        }
+        journal
+    }
+
+    /// Takes in the events every service has recorded since the last collection, in the order they happened,
+    /// and returns them.
+    pub fn collect(&mut self, services: &mut HashMap<String, ServiceEntry>) -> Vec<ServiceEvent> {
+        let mut new_events: Vec<ServiceEvent> = services
+            .values_mut()
+            .flat_map(|service| service.events.drain(..))
+            .collect();
+        if new_events.is_empty() {
+            return new_events;
+        }
+        // events of one service are already in order, and the sort is stable
+        new_events.sort_by_key(|event| event.time);
+        self.push_all(new_events.clone());
+        if self.persist {
+            self.save();
+        }
+        new_events
+    }
+
+    /// Returns the most recent `limit` events, or all of them if `limit` is 0, oldest first.
+    /// If `service_name` is given, only that service's events are returned.
+    pub fn query(&self, service_name: Option<&str>, limit: usize) -> Vec<ServiceEvent> {
+        let matching: Vec<&ServiceEvent> = self
+            .events
+            .iter()
+            .filter(|event| service_name.map_or(true, |name| event.service == name))
+            .collect();
+        let skip = if limit == 0 { 0 } else { matching.len().saturating_sub(limit) };
+        matching.into_iter().skip(skip).cloned().collect()
+    }
+
+    fn push_all(&mut self, events: Vec<ServiceEvent>) {
+        self.events.extend(events);
+        while self.events.len() > self.capacity {
+            self.events.pop_front();
+        }
+    }
+
+    fn save(&self) {
+        let file = EventsFile {
+            event: self.events.iter().cloned().collect(),
+        };
+        match toml::to_string(&file) {
+            Ok(toml_str) => {
+                if let Err(err) = fs::write(EVENTS_PATH, toml_str) {
+                    warn!("unable to write events to {}: {}", EVENTS_PATH, err);
+                }
+            }
+            Err(err) => warn!("unable to encode events: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Service;
+    use shared::{EventKind, ServiceState};
+
+    fn journal(capacity: usize) -> EventJournal {
+        EventJournal::new(&MonitorConfig { event_capacity: capacity, ..Default::default() })
+    }
+
+    fn event(service: &str, time: i64) -> ServiceEvent {
+        ServiceEvent {
+            time,
+            service: service.to_string(),
+            kind: EventKind::Start,
+            old_state: ServiceState::Stopped,
+            new_state: ServiceState::Running,
+            reason: String::new(),
+        }
+    }
+
+    fn times(events: &[ServiceEvent]) -> Vec<i64> {
+        events.iter().map(|event| event.time).collect()
+    }
+
+    #[test]
+    fn oldest_events_are_dropped_over_capacity() {
+        let mut journal = journal(3);
+        journal.push_all((1..=2).map(|time| event("a", time)).collect());
+        journal.push_all((3..=5).map(|time| event("a", time)).collect());
+        assert_eq!(times(&journal.query(None, 0)), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn query_returns_the_most_recent_matching_events() {
+        let mut journal = journal(10);
+        journal.push_all(vec![event("a", 1), event("b", 2), event("a", 3), event("b", 4), event("a", 5)]);
+        assert_eq!(times(&journal.query(None, 2)), vec![4, 5]);
+        assert_eq!(times(&journal.query(Some("a"), 2)), vec![3, 5]);
+        assert_eq!(times(&journal.query(Some("b"), 0)), vec![2, 4]);
+        assert_eq!(times(&journal.query(Some("a"), 10)), vec![1, 3, 5]);
+        assert!(journal.query(Some("c"), 0).is_empty());
+    }
+
+    #[test]
+    fn collected_events_are_in_time_order() {
+        let mut journal = journal(10);
+        let mut services: HashMap<String, ServiceEntry> = ["a", "b"]
+            .iter()
+            .map(|name| (name.to_string(), ServiceEntry::new(Service { name: name.to_string(), ..Default::default() })))
+            .collect();
+        services.get_mut("a").unwrap().events = vec![event("a", 1), event("a", 4)];
+        services.get_mut("b").unwrap().events = vec![event("b", 2), event("b", 3)];
+        assert_eq!(times(&journal.collect(&mut services)), vec![1, 2, 3, 4]);
+        assert!(services.values().all(|service| service.events.is_empty()));
+        assert!(journal.collect(&mut services).is_empty());
+        assert_eq!(times(&journal.query(None, 0)), vec![1, 2, 3, 4]);
+    }
+}
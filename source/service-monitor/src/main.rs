@@ -1,27 +1,39 @@
 use chrono::prelude::*;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use libredox::{
     errno::*,
     error::*,
-    flag::*,
+    flag::{O_RDONLY, O_RDWR},
+    Fd,
 };
 use log::{error, info, warn};
 use redox_log::{OutputBuilder, RedoxLogger};
+use events::EventJournal;
 use redox_scheme::{RequestKind, SignalBehavior, Socket};
 use scheme::SMScheme;
-use shared::{CommandResponse, RegistryCommand, SMCommand, ServiceDetailStats, ServiceRuntimeStats, TOMLMessage};
+use snapshot::{RuntimeSnapshot, SavedProcess};
+use stats::StatsStore;
+use syscall::{data::{Event, TimeSpec}, CLOCK_MONOTONIC, EVENT_READ};
+use shared::{CommandResponse, DiffEntry, EventKind, HealthStatus, RegistryCommand, RegistryDiff, SMCommand, ServiceDetailStats, ServiceRuntimeStats, ServiceState, TOMLMessage};
 
 use std::{
+    collections::BTreeMap,
+    os::unix::process::ExitStatusExt,
     str,
-    sync::mpsc,
     thread,
-    time::Duration,
+    sync::mpsc,
+    time::{Duration, Instant},
 };
+mod deps;
+mod events;
 mod registry;
 mod scheme;
+mod snapshot;
+mod stats;
+mod worker;
 use registry::{
-    add_entry, add_hash_entry, edit_entry, edit_hash_entry, read_registry, rm_entry, rm_hash_entry,
-    view_entry, ServiceEntry,
+    add_entry, add_hash_entry, changed_fields, correct_entries, edit_entry, edit_hash_entry, read_config, read_registry, read_registry_configs, read_targets,
+    rm_entry, rm_hash_entry, view_entry, ExitInfo, MonitorConfig, ReadyCheck, RestartPolicy, ServiceEntry, Target,
 };
 
 fn main() {
@@ -38,18 +50,36 @@ fn main() {
 
     redox_daemon::Daemon::new(move |daemon| {
         let name = "service-monitor";
+        // the scheme is non-blocking so the main loop can run supervision ticks between requests
         let socket =
-            Socket::create(name).expect("service-monitor: failed to create Service Monitor scheme");
-
-        let mut sm_scheme = SMScheme::new();
+            Socket::nonblock(name).expect("service-monitor: failed to create Service Monitor scheme");
+        // the main loop sleeps until a request arrives on the SM scheme or the supervision timer fires
+        let event_queue = Fd::open("/scheme/event", O_RDWR, 0)
+            .expect("service-monitor: failed to open an event queue");
+        let timer = Fd::open(&format!("/scheme/time/{}", CLOCK_MONOTONIC), O_RDWR, 0)
+            .expect("service-monitor: failed to open the supervision timer");
+        watch_fd(&event_queue, socket.inner().raw(), SCHEME_EVENT);
+        watch_fd(&event_queue, timer.raw(), TIMER_EVENT);
 
         // make list of managed services
         let mut services: HashMap<String, ServiceEntry> = read_registry();
+        correct_entries(services.values_mut().map(|service| &mut service.config));
+        let mut targets: HashMap<String, Target> = read_targets().unwrap_or_else(|err| panic!("{}", err));
+        let config: MonitorConfig = read_config();
+        let supervise_interval = Duration::from_millis(config.supervise_interval_ms);
+        arm_timer(&timer, supervise_interval);
+        let mut sm_scheme = SMScheme::new(config.event_capacity);
+        let mut journal = EventJournal::new(&config);
+        // bring back the lifetime stats from before the service monitor last exited
+        let mut stats = StatsStore::load(&mut services);
 
-        // start dependencies
-        for service in services.values_mut() {
-            let _ = start(service);
-        }
+        // take back the services that outlived the previous service monitor, so they aren't launched twice
+        let (mut snapshot, processes) = RuntimeSnapshot::load();
+        adopt_all(&mut services, processes);
+
+        // start services in dependency order
+        start_all(&mut services, config.max_parallel_starts);
+        snapshot.save(&mut services);
 
         info!(
             "service-monitor daemonized with pid: {}",
@@ -58,208 +88,660 @@ fn main() {
         daemon
             .ready()
             .expect("service-monitor: failed to notify parent");
-        // TODO move dep loop here
         loop {
-            eval_cmd(&mut services, &mut sm_scheme);
-            // The following is for handling requests to the SM scheme
-            // Redox does timers with the timer scheme according to docs https://doc.redox-os.org/book/event-scheme.html
-            // not sure if that is still how it works or not, but seems similar to this code
-
-            let Some(request) = socket
-                .next_request(SignalBehavior::Restart)
-                .expect("service-monitor: failed to read events from Service Monitor scheme")
-            else {
-                warn!("exiting Service Monitor");
-                std::process::exit(0);
-            };
+            // handle every request that is waiting on the SM scheme
+            loop {
+                let request = match socket.next_request(SignalBehavior::Restart) {
+                    Ok(Some(request)) => request,
+                    Ok(None) => {
+                        warn!("exiting Service Monitor");
+                        std::process::exit(0);
+                    }
+                    Err(err) if err.errno == EAGAIN || err.errno == EWOULDBLOCK => break,
+                    Err(err) => {
+                        panic!("service-monitor: failed to read events from Service Monitor scheme: {}", err)
+                    }
+                };
 
-            match request.kind() {
-                RequestKind::Call(request) => {
-                    // handle request
-                    let response = request.handle_scheme(&mut sm_scheme);
-                    socket
-                        .write_responses(&[response], SignalBehavior::Restart)
-                        .expect(
-                            "service-monitor: failed to write responses to Service Monitor scheme",
-                        );
+                match request.kind() {
+                    RequestKind::Call(request) => {
+                        // handle request
+                        let response = request.handle_scheme(&mut sm_scheme);
+                        socket
+                            .write_responses(&[response], SignalBehavior::Restart)
+                            .expect(
+                                "service-monitor: failed to write responses to Service Monitor scheme",
+                            );
+                    }
+                    _ => (),
+                }
+                // run the command (if any) so its response is ready for the client's next read
+                eval_cmd(&mut services, &mut targets, &config, &mut sm_scheme, &mut journal);
+            }
+
+            supervise(&mut services, &config);
+            let new_events = journal.collect(&mut services);
+            sm_scheme.publish(&new_events);
+            // wake up anyone waiting on a handle that has something to read now
+            for id in sm_scheme.take_readable() {
+                if let Err(err) = socket.post_fevent(id, EVENT_READ.bits()) {
+                    warn!("failed to post event for handle {}: {}", id, err);
                 }
-                _ => (),
             }
+            stats.save(&mut services);
+            snapshot.save(&mut services);
+            wait_for_event(&event_queue, &timer, supervise_interval);
         }
     })
     .expect("service-monitor: failed to daemonize");
 }
 
-/// Executes then clears the command stored in the service-monitor's scheme.
-fn eval_cmd(services: &mut HashMap<String, ServiceEntry>, sm_scheme: &mut SMScheme) {
-    let mut result: Result<Option<TOMLMessage>, Option<TOMLMessage>>;
-    match &(sm_scheme.cmd) {
-        Some(SMCommand::Stop { service_name }) => {
-            if let Some(service) = services.get_mut(service_name) {
-                // info!("Stopping '{}'", service.config.name);
-                result = stop(service);
-                // if we stopped the service successfully, update its entry in the internal map
-                if let Ok(_k) = result.as_mut() {
-                    let mut registry = read_registry();
-                    let registry_value = registry.remove(service_name);
-                    if let Some(s) = registry_value {
-                        services.insert(service_name.clone(), s);
-                    } else {
-                        services.remove(service_name);
-                    }
-                };
-            } else {
-                warn!("stop failed: no service named '{}'", service_name);
-                result = Err(Some(TOMLMessage::String(format!("Unable to stop '{}': No such service", service_name))));
+/// Event queue data for a request waiting on the SM scheme.
+const SCHEME_EVENT: usize = 0;
+/// Event queue data for the supervision timer firing.
+const TIMER_EVENT: usize = 1;
+
+/// Registers `fd` with the main loop's event queue, which returns `data` whenever `fd` becomes readable.
+fn watch_fd(event_queue: &Fd, fd: usize, data: usize) {
+    let event = Event { id: fd, flags: EVENT_READ, data };
+    event_queue
+        .write(&event)
+        .expect("service-monitor: failed to register with the event queue");
+}
+
+/// Sets the supervision timer to fire `interval` from now.
+fn arm_timer(timer: &Fd, interval: Duration) {
+    let mut time = TimeSpec::default();
+    timer
+        .read(&mut time)
+        .expect("service-monitor: failed to read the supervision timer");
+    let nanos = time.tv_nsec as i64 + interval.as_nanos() as i64;
+    time.tv_sec += nanos / 1_000_000_000;
+    time.tv_nsec = (nanos % 1_000_000_000) as i32;
+    timer
+        .write(&time)
+        .expect("service-monitor: failed to set the supervision timer");
+}
+
+/// Blocks until a request arrives on the SM scheme or the supervision timer fires, re-arming the timer when it does.
+fn wait_for_event(event_queue: &Fd, timer: &Fd, interval: Duration) {
+    let mut event = Event::default();
+    match event_queue.read(&mut event) {
+        Ok(_) if event.data == TIMER_EVENT => arm_timer(timer, interval),
+        Ok(_) => (),
+        Err(err) if err.is_interrupt() => (),
+        Err(err) => panic!("service-monitor: failed to wait on the event queue: {}", err),
+    }
+}
+
+/// Runs one supervision pass over the running managed services.
+///
+/// Nothing here waits on a service: restarts are launched and then polled on later passes by [poll_restart].
+/// Each service whose health check interval has elapsed has its management subschemes polled,
+/// which records its last response time. A service that times out is recovered by [read_helper].
+fn supervise(services: &mut HashMap<String, ServiceEntry>, config: &MonitorConfig) {
+    let now = Local::now().timestamp_millis();
+    for service in services.values_mut() {
+        // restarts that are waiting for the service to become ready
+        if service.restart_deadline != 0 {
+            poll_restart(service, now);
+            continue;
+        }
+        // restarts that were delayed by a backoff
+        if !service.has_process() && service.next_retry_time != 0 && now >= service.next_retry_time {
+            info!("retrying restart of '{}'", service.config.name);
+            restart(service);
+            continue;
+        }
+        if !service.has_process() {
+            stop_health_check(service);
+            continue;
+        }
+        if check_exit(service) {
+            stop_health_check(service);
+            continue;
+        }
+        run_health_check(service, now);
+        let probe_interval = service.config.probe_interval_ms;
+        // a failed monitor-only service is not recovered, so probing it would only record more failures
+        let probing = service.state != ServiceState::Failed && !service.config.probe.is_empty() && probe_interval != 0;
+        if probing && now - service.last_probe >= probe_interval as i64 {
+            service.last_probe = now;
+            if !run_probes(service) {
+                continue;
             }
         }
-        Some(SMCommand::Start { service_name }) => {
-            if let Some(service) = services.get_mut(service_name) {
-                //info!("Starting '{}'", service.config.name);
-                result = start(service);
-            } else {
-                warn!("start failed: no service named '{}'", service_name);
-                result = Err(Some(TOMLMessage::String(format!("Unable to start '{}': No such service", service_name))));
+        if !service.config.is_managed() {
+            continue;
+        }
+        let interval = service.config.health_interval_ms.unwrap_or(config.health_interval_ms);
+        if interval == 0 || now - service.last_health_check < interval as i64 {
+            continue;
+        }
+        // a failed monitor-only service keeps its one worker, which stays stuck until the service answers again
+        if service.state == ServiceState::Failed && service.worker.as_ref().is_some_and(|worker| worker.is_stuck()) {
+            continue;
+        }
+        service.last_health_check = now;
+        if !update_service_info(service) {
+            warn!("health check on '{}' failed", service.config.name);
+            service.record_event(EventKind::Health, "health check failed");
+        }
+    }
+
+    // services that were removed from the registry while running are dropped once they stop
+    services.retain(|name, service| {
+        let keep = !service.retired || service.has_process();
+        if !keep {
+            info!("'{}' stopped and was removed from the registry, dropping it", name);
+        }
+        keep
+    });
+}
+
+/// Drives a running service's `health_check` command without waiting on it: collects the result of a run
+/// that finished, kills a run that took longer than its timeout, and starts a new run once the interval has passed.
+fn run_health_check(service: &mut ServiceEntry, now: i64) {
+    let Some(check) = service.config.health_check.clone() else {
+        return;
+    };
+    if let Some(run) = service.health_check_run.as_mut() {
+        let timed_out = now - service.health_check_started >= check.timeout_ms as i64;
+        let (health, output) = match run.child.try_wait() {
+            Ok(Some(_status)) if !run.output_read() && !timed_out => return,
+            Ok(Some(status)) => (HealthStatus::from_exit_code(status.code()), run.output()),
+            Ok(None) if !timed_out => return,
+            Ok(None) => {
+                let _ = run.child.kill();
+                let _ = run.child.wait();
+                (HealthStatus::Unhealthy, format!("timed out after {} ms", check.timeout_ms))
             }
+            Err(e) => (HealthStatus::Unhealthy, format!("failed to wait for the health check: {}", e)),
+        };
+        service.health_check_run = None;
+        set_health(service, health, output);
+        return;
+    }
+
+    if check.interval_ms == 0 || now - service.health_check_started < check.interval_ms as i64 {
+        return;
+    }
+    service.health_check_started = now;
+    match check.spawn() {
+        Ok(run) => service.health_check_run = Some(run),
+        Err(e) => set_health(service, HealthStatus::Unhealthy, format!("failed to run '{}': {}", check.exec, e)),
+    }
+}
+
+/// Kills the health check command of a service that is no longer running, if one is still running.
+fn stop_health_check(service: &mut ServiceEntry) {
+    if let Some(mut run) = service.health_check_run.take() {
+        let _ = run.child.kill();
+        let _ = run.child.wait();
+    }
+}
+
+/// Records the result of a service's health check command, with an event whenever the result changes.
+fn set_health(service: &mut ServiceEntry, health: HealthStatus, output: String) {
+    if health != service.health {
+        let reason = if output.is_empty() {
+            format!("health check: {} -> {}", service.health, health)
+        } else {
+            format!("health check: {} -> {}: {}", service.health, health, output.lines().next().unwrap_or_default())
+        };
+        if health == HealthStatus::Healthy {
+            info!("'{}' {}", service.config.name, reason);
+        } else {
+            warn!("'{}' {}", service.config.name, reason);
+        }
+        service.record_event(EventKind::Health, &reason);
+    }
+    service.health = health;
+    service.health_output = output;
+    service.health_time = Local::now().timestamp_millis();
+}
+
+/// Runs every probe of a running service, stopping at the first one that fails.
+///
+/// A service whose probes fail `probe_failure_threshold` times in a row is recovered according to its restart policy.
+/// Returns false if the service was recovered.
+fn run_probes(service: &mut ServiceEntry) -> bool {
+    let mut failure: Option<String> = None;
+    for probe in service.config.probe.clone() {
+        let timeout_ms = probe.timeout_ms.unwrap_or(service.config.timeout_ms);
+        failure = match service.worker().probe(&probe, Duration::from_millis(timeout_ms)) {
+            Some(Ok(())) => continue,
+            Some(Err(e)) => Some(format!("probe {} failed: {}", probe, e)),
+            // the worker stays stuck inside the service's scheme until the service is recovered
+            None => Some(format!("probe {} timed out after {} ms", probe, timeout_ms)),
+        };
+        break;
+    }
+
+    let Some(reason) = failure else {
+        if service.probe_failures > 0 {
+            info!("probes on '{}' are passing again", service.config.name);
+            service.record_event(EventKind::Health, "probes passing again");
+            service.probe_failures = 0;
         }
-        Some(SMCommand::List) => {
-            result = list(services)
+        return true;
+    };
+    service.probe_failures += 1;
+    warn!("'{}': {} ({} of {})", service.config.name, reason, service.probe_failures, service.config.probe_failure_threshold);
+    service.record_event(EventKind::Health, &reason);
+    if service.probe_failures < service.config.probe_failure_threshold {
+        return true;
+    }
+    error!("'{}' failed {} probes in a row, recovering it", service.config.name, service.probe_failures);
+    service.probe_failures = 0;
+    recover(service);
+    false
+}
+
+/// Checks whether a service's process has exited. If it has, the exit is recorded, the service is moved
+/// to the stopped or failed state, and its restart policy is applied. An exit whose status is unknown
+/// is treated as a stop, so only the `always` policy restarts it.
+///
+/// Exits are detected by reaping processes that are children of the service monitor, and by checking
+/// whether the pid still exists for the rest. The pid of an unmanaged service that daemonized is unknown,
+/// so its exit cannot be detected. An unmanaged service's process that exits cleanly within `start_timeout_ms`
+/// of being launched while its scheme is served daemonized, and is not counted as an exit.
+///
+/// Returns true if the process exited.
+fn check_exit(service: &mut ServiceEntry) -> bool {
+    let now = Local::now().timestamp_millis();
+    let exit = match service.child.as_mut() {
+        Some(child) => match child.try_wait() {
+            Ok(Some(status)) => ExitInfo { time: now, code: status.code(), signal: status.signal() },
+            _ => {
+                if service.daemonize_deadline != 0 && now > service.daemonize_deadline {
+                    service.daemonize_deadline = 0;
+                    info!("'{}' is running in the foreground with pid {}", service.config.name, service.pid);
+                }
+                return false;
+            }
         },
-        Some(SMCommand::Clear { service_name }) => {
-            if let Some(service) = services.get_mut(service_name) {
-                //info!("Clearing short-term stats for '{}'", service.config.name);
-                result = clear(service);
-            } else {
-                warn!("clear failed: no service named '{}'", service_name);
-                result = Err(Some(TOMLMessage::String(format!("Unable to clear '{}': No such service", service_name))));
+        None => {
+            if service.pid == 0 {
+                return false;
+            }
+            match syscall::kill(service.pid, 0) {
+                Err(err) if err.errno == syscall::ESRCH => ExitInfo { time: now, code: None, signal: None },
+                _ => return false,
             }
         }
-        Some(SMCommand::Info { service_name }) => {
-            if let Some(service) = services.get_mut(service_name) {
-                //info!("Finding information for '{}'", service.config.name);
-                result = info(service);
-            } else {
-                warn!("info failed: no service named '{}'", service_name);
-                result = Err(Some(TOMLMessage::String(format!("Unable to get info for '{}': No such service", service_name))));
+    };
+    service.child = None;
+    service.worker = None;
+    let name = service.config.name.clone();
+
+    // an unmanaged service's process that exits cleanly soon after it was launched may have forked into the background
+    let may_have_daemonized = service.daemonize_deadline != 0 && now <= service.daemonize_deadline;
+    service.daemonize_deadline = 0;
+    if may_have_daemonized && exit.code == Some(0) && scheme_is_served(service) {
+        info!("'{}' daemonized", name);
+        service.pid = 0;
+        service.snapshot_dirty = true;
+        service.record_event(EventKind::Start, "daemonized, its pid is unknown");
+        return false;
+    }
+
+    let failed = exit.failed();
+    if failed {
+        error!("'{}' (pid {}) {}", name, service.pid, exit);
+    } else {
+        info!("'{}' (pid {}) {}", name, service.pid, exit);
+    }
+    let reason = exit.to_string();
+    service.last_exit = Some(exit);
+    // the counts last read from the process that exited are kept in the totals
+    service.fold_counts();
+
+    let state = if failed { ServiceState::Failed } else { ServiceState::Stopped };
+    service.set_state(state, EventKind::Exit, &reason);
+    if service.config.restart.restarts_after_exit(failed) {
+        schedule_restart(service);
+    }
+    true
+}
+
+/// Adopts the processes recorded by the previous service monitor that are still alive.
+///
+/// A managed service is alive if its scheme still answers a read of its "pid" subscheme. An unmanaged service
+/// is alive if its pid still exists or, if it daemonized and its pid is unknown, if its scheme can still be opened.
+fn adopt_all(services: &mut HashMap<String, ServiceEntry>, processes: BTreeMap<String, SavedProcess>) {
+    for (name, saved) in processes {
+        let Some(service) = services.get_mut(&name) else {
+            info!("not adopting '{}': no longer in the registry", name);
+            continue;
+        };
+        match adopt(service, &saved) {
+            Ok(()) => info!("adopted '{}' with pid {}", name, service.pid),
+            Err(reason) => info!("not adopting '{}': {}", name, reason),
+        }
+    }
+}
+
+/// Takes over the running process of a service from the previous service monitor, if it is still alive.
+fn adopt(service: &mut ServiceEntry, saved: &SavedProcess) -> std::result::Result<(), String> {
+    if saved.scheme_path != service.config.scheme_path {
+        return Err(format!("its scheme path changed from '{}'", saved.scheme_path));
+    }
+    let pid = if service.config.is_managed() {
+        read_pid(service)?
+    } else if saved.pid != 0 {
+        if let Err(err) = syscall::kill(saved.pid, 0) {
+            if err.errno == syscall::ESRCH {
+                return Err(format!("pid {} has exited", saved.pid));
             }
         }
-        Some(SMCommand::Registry { subcommand }) => {
-            match subcommand {
-                RegistryCommand::View { service_name } => {
-                    result = view_entry(service_name);
+        saved.pid
+    } else {
+        if service.config.scheme_path.is_empty() {
+            return Err("it daemonized and has no scheme to probe".to_string());
+        }
+        let fd = libredox::call::open(service.config.scheme_path.clone(), O_RDONLY, 0)
+            .map_err(|e| format!("failed to open '{}': {}", service.config.scheme_path, e))?;
+        let _ = libredox::call::close(fd);
+        0
+    };
+
+    service.pid = pid;
+    if pid == saved.pid {
+        service.time_started = saved.time_started;
+        service.time_init = saved.time_init;
+    } else {
+        // the process that owns the scheme isn't the one that was recorded, so its start time is unknown
+        warn!("'{}' is now served by pid {} instead of pid {}", service.config.name, pid, saved.pid);
+        service.time_started = Local::now().timestamp_millis();
+        service.time_init = service.time_started;
+    }
+    service.snapshot_dirty = true;
+    // a service caught starting or stopping is running now, and one the previous service monitor gave up on stays failed
+    let state = match saved.state {
+        ServiceState::Failed | ServiceState::Restarted => saved.state,
+        _ => ServiceState::Running,
+    };
+    service.set_state(state, EventKind::Adopt, &format!("still running with pid {} after the service monitor restarted", pid));
+    Ok(())
+}
+
+/// Starts every service in the registry after its dependencies have started.
+///
+/// Services with unknown dependencies or in a dependency cycle are never started, a service
+/// is skipped if any of its dependencies failed to start, and services that were adopted are left alone.
+fn start_all(services: &mut HashMap<String, ServiceEntry>, max_parallel: usize) {
+    let plan = deps::boot_plan(services);
+    for (name, reason) in plan.rejected {
+        error!("not starting '{}': {}", name, reason);
+    }
+
+    let order: Vec<String> = plan.order.into_iter().filter(|name| services[name.as_str()].config.autostart).collect();
+    let boot_started = Instant::now();
+    let results = start_parallel(services, &order, max_parallel);
+    let mut started: Vec<&String> = Vec::new();
+    for (name, result) in &results {
+        match result {
+            Ok(_) => started.push(name),
+            Err(msg) => error!("'{}' failed to start during boot: {}", name, message_text(msg)),
+        }
+    }
+    // how long booting would have taken with every service started one at a time
+    let sequential: i64 = started.iter().map(|name| services[name.as_str()].time_ready - services[name.as_str()].time_started).sum();
+    info!(
+        "started {} of {} services in {} ms, up to {} at once ({} ms one at a time)",
+        started.len(),
+        results.len(),
+        boot_started.elapsed().as_millis(),
+        max_parallel.max(1),
+        sequential
+    );
+}
+
+/// The outcome of starting a service, as returned by [start].
+type StartResult = Result<Option<TOMLMessage>, Option<TOMLMessage>>;
+
+/// Starts the services in `order`, which must be in dependency order, with up to `max_parallel` of them starting at once.
+///
+/// Each service is started once all of its dependencies are ready, and is not started at all if one of them
+/// failed to start or is not running and not in `order`. Services that already have a process are left alone.
+/// Returns the result for every other service, in the order they finished.
+fn start_parallel(services: &mut HashMap<String, ServiceEntry>, order: &[String], max_parallel: usize) -> Vec<(String, StartResult)> {
+    let mut pending: Vec<String> = order.iter().filter(|name| !services[name.as_str()].has_process()).cloned().collect();
+    let mut starting: HashSet<String> = HashSet::new();
+    let mut failed: HashSet<String> = HashSet::new();
+    let mut results: Vec<(String, StartResult)> = Vec::new();
+
+    thread::scope(|scope| {
+        let (done, finished) = mpsc::channel::<(ServiceEntry, StartResult)>();
+        loop {
+            // hand out every service whose dependencies are ready, while there is room
+            let mut i = 0;
+            while i < pending.len() && starting.len() < max_parallel.max(1) {
+                if let Some((dep, reason)) = deps::start_blocked_by(services, &pending[i], &starting, &pending, &failed) {
+                    let name = pending.remove(i);
+                    warn!("skipping '{}': dependency '{}' {}", name, dep, reason);
+                    let msg = format!("Unable to start '{}': dependency '{}' {}", name, dep, reason);
+                    failed.insert(name.clone());
+                    results.push((name, Err(Some(TOMLMessage::String(msg)))));
+                } else if deps::dependencies_ready(services, &pending[i]) {
+                    let name = pending.remove(i);
+                    // the entry is handed to the thread starting it, and comes back when it is done
+                    let mut service = services.remove(&name).unwrap();
+                    starting.insert(name);
+                    let done = done.clone();
+                    scope.spawn(move || {
+                        let result = start(&mut service);
+                        let _ = done.send((service, result));
+                    });
+                } else {
+                    i += 1;
+                }
+            }
+            // pending services are only left waiting on services that are starting
+            if starting.is_empty() {
+                break;
+            }
+            let Ok((service, result)) = finished.recv() else {
+                break;
+            };
+            let name = service.config.name.clone();
+            starting.remove(&name);
+            if result.is_err() {
+                failed.insert(name.clone());
+            }
+            services.insert(name.clone(), service);
+            results.push((name, result));
+        }
+    });
+    results
+}
+
+/// Executes every command waiting in the service-monitor's scheme, writing each response back
+/// to the handle the command was written to.
+fn eval_cmd(
+    services: &mut HashMap<String, ServiceEntry>,
+    targets: &mut HashMap<String, Target>,
+    config: &MonitorConfig,
+    sm_scheme: &mut SMScheme,
+    journal: &mut EventJournal,
+) {
+    while let Some((id, cmd)) = sm_scheme.take_command() {
+        let mut result: Result<Option<TOMLMessage>, Option<TOMLMessage>>;
+        match &cmd {
+            SMCommand::Stop { service_name, cascade } => {
+                if let Some(target) = service_name.strip_prefix('@') {
+                    result = stop_target(services, targets, target, *cascade);
+                } else if services.contains_key(service_name) {
+                    // info!("Stopping '{}'", service_name);
+                    result = stop_with_dependents(services, service_name, *cascade);
+                } else {
+                    warn!("stop failed: no service named '{}'", service_name);
+                    result = Err(Some(TOMLMessage::String(format!("Unable to stop '{}': No such service", service_name))));
+                }
+            }
+            SMCommand::Start { service_name, force } => {
+                if let Some(target) = service_name.strip_prefix('@') {
+                    result = start_target(services, targets, target, config.max_parallel_starts);
+                } else if services.contains_key(service_name) {
+                    //info!("Starting '{}'", service_name);
+                    result = start_with_deps(services, service_name, *force);
+                } else {
+                    warn!("start failed: no service named '{}'", service_name);
+                    result = Err(Some(TOMLMessage::String(format!("Unable to start '{}': No such service", service_name))));
                 }
-                RegistryCommand::Add {
-                    service_name,
-                    old,
-                    args,
-                    manual_override,
-                    depends,
-                    scheme_path,
-                } => {
-                    let r#type = if *old { "unmanaged" } else { "daemon" };
-                    // ! this overrides existing entries
-                    result = add_entry(
+            }
+            SMCommand::Restart { service_name, restore } => {
+                if services.contains_key(service_name) {
+                    result = restart_service(services, service_name, *restore);
+                } else {
+                    warn!("restart failed: no service named '{}'", service_name);
+                    result = Err(Some(TOMLMessage::String(format!("Unable to restart '{}': No such service", service_name))));
+                }
+            }
+            SMCommand::List { target: None } => {
+                result = list(services, None)
+            },
+            SMCommand::List { target: Some(target) } => {
+                let target = target.strip_prefix('@').unwrap_or(target);
+                result = resolve_target(services, targets, target).and_then(|(_, group)| list(services, Some(&group)))
+            },
+            SMCommand::Reload => {
+                result = reload(services, targets)
+            },
+            SMCommand::Events { service_name, limit } => {
+                let new_events = journal.collect(services);
+                sm_scheme.publish(&new_events);
+                let events = journal.query(service_name.as_deref(), *limit);
+                match service_name {
+                    // a service that was removed can still have events in the journal
+                    Some(name) if events.is_empty() && !services.contains_key(name) => {
+                        warn!("events failed: no service named '{}'", name);
+                        result = Err(Some(TOMLMessage::String(format!("Unable to show events for '{}': No such service", name))));
+                    }
+                    _ => result = Ok(Some(TOMLMessage::Events(events))),
+                }
+            }
+            SMCommand::Clear { service_name } => {
+                if let Some(service) = services.get_mut(service_name) {
+                    //info!("Clearing short-term stats for '{}'", service.config.name);
+                    result = clear(service);
+                } else {
+                    warn!("clear failed: no service named '{}'", service_name);
+                    result = Err(Some(TOMLMessage::String(format!("Unable to clear '{}': No such service", service_name))));
+                }
+            }
+            SMCommand::Info { service_name } => {
+                if let Some(service) = services.get_mut(service_name) {
+                    //info!("Finding information for '{}'", service.config.name);
+                    result = info(service);
+                } else {
+                    warn!("info failed: no service named '{}'", service_name);
+                    result = Err(Some(TOMLMessage::String(format!("Unable to get info for '{}': No such service", service_name))));
+                }
+            }
+            SMCommand::Registry { subcommand } => {
+                match subcommand {
+                    RegistryCommand::View { service_name } => {
+                        result = view_entry(service_name);
+                    }
+                    RegistryCommand::Add {
                         service_name,
-                        r#type,
-                        args.as_ref().unwrap(),
-                        *manual_override,
+                        old,
+                        args,
+                        manual_override,
+                        depends,
                         scheme_path,
-                        depends.as_ref().unwrap(),
-                    );
-                    match result {
-                        Ok(o) => {
-                            // ! but this doesn't
-                            result = add_hash_entry(
-                                service_name,
-                                r#type,
-                                args.as_ref().unwrap(),
-                                *manual_override,
-                                scheme_path,
-                                depends.as_ref().unwrap(),
-                                services,
-                            ).map(|_| o);
+                    } => {
+                        let r#type = if *old { "unmanaged" } else { "daemon" };
+                        // ! this overrides existing entries
+                        result = add_entry(
+                            service_name,
+                            r#type,
+                            args.as_ref().unwrap(),
+                            *manual_override,
+                            scheme_path,
+                            depends.as_ref().unwrap(),
+                        );
+                        match result {
+                            Ok(o) => {
+                                // ! but this doesn't
+                                result = add_hash_entry(
+                                    service_name,
+                                    r#type,
+                                    args.as_ref().unwrap(),
+                                    *manual_override,
+                                    scheme_path,
+                                    depends.as_ref().unwrap(),
+                                    services,
+                                ).map(|_| o);
+                                if let Some(service) = services.get_mut(service_name.as_str()) {
+                                    correct_entries(std::iter::once(&mut service.config));
+                                }
+                            }
+                            _ => {}
                         }
-                        _ => {}
                     }
-                }
-                RegistryCommand::Remove { service_name } => {
-                    result = rm_entry(service_name);
-                    match result {
-                        Ok(o) => {
-                            result = rm_hash_entry(services, service_name).map(|_| o);
+                    RegistryCommand::Remove { service_name } => {
+                        result = rm_entry(service_name);
+                        match result {
+                            Ok(o) => {
+                                result = rm_hash_entry(services, service_name).map(|_| o);
+                            }
+                            _ => {}
                         }
-                        _ => {}
                     }
-                }
-                RegistryCommand::Edit {
-                    service_name,
-                    old,
-                    edit_args,
-                    scheme_path,
-                    depends,
-                } => {
-                    result = edit_entry(
+                    RegistryCommand::Edit {
                         service_name,
-                        *old,
-                        edit_args.as_ref().unwrap(),
+                        old,
+                        edit_args,
                         scheme_path,
-                        depends.as_ref().unwrap(),
-                    );
-                    match result {
-                        Ok(o) => {
-                            result = edit_hash_entry(
-                                services,
-                                service_name,
-                                *old,
-                                edit_args.as_ref().unwrap(),
-                                scheme_path,
-                                depends.as_ref().unwrap(),
-                            ).map(|_| o);
+                        depends,
+                    } => {
+                        result = edit_entry(
+                            service_name,
+                            *old,
+                            edit_args.as_ref().unwrap(),
+                            scheme_path,
+                            depends.as_ref().unwrap(),
+                        );
+                        match result {
+                            Ok(o) => {
+                                result = edit_hash_entry(
+                                    services,
+                                    service_name,
+                                    *old,
+                                    edit_args.as_ref().unwrap(),
+                                    scheme_path,
+                                    depends.as_ref().unwrap(),
+                                ).map(|_| o);
+                            }
+                            _ => {}
                         }
-                        _ => {}
                     }
                 }
-            }
-        },
-        None => {
-            // if we don't do this, writing response will crash service-monitor
-            return;
+            },
         }
-    }
 
-    // write back a response to the service monitor's scheme
-    match result {
-        Ok(msg) => {
-            let _ = sm_scheme.write_response(
-                &CommandResponse::new(
-                    sm_scheme.cmd.as_ref().unwrap(),
-                    true,
-                    msg
-                )
-            );
-        }
-        Err(msg) => {
-            let _ = sm_scheme.write_response(
-                &CommandResponse::new(
-                    sm_scheme.cmd.as_ref().unwrap(),
-                    false,
-                    msg
-                )
-            );
+        // write back a response to the handle the command came from
+        let response = match result {
+            Ok(msg) => CommandResponse::new(&cmd, true, msg),
+            Err(msg) => CommandResponse::new(&cmd, false, msg),
+        };
+        if let Err(e) = sm_scheme.write_response(id, &response) {
+            warn!("{}", e);
         }
     }
-
-    // reset the current command value
-    sm_scheme.cmd = None;
 }
 
 /// Updates runtime info about a service.
-fn update_service_info(service: &mut ServiceEntry) {
+/// Returns true and records the service's last response time if every read succeeded.
+fn update_service_info(service: &mut ServiceEntry) -> bool {
     //info!("Updating information for: {}", service.config.name);
 
     let read_buffer: &mut [u8] = &mut [b'0'; 48];
 
-    let _ = read_helper(service, read_buffer, "message");
+    let message_res = read_helper(service, read_buffer, "message");
     // grab the string
     let mut message_string = match str::from_utf8(&read_buffer[0..32]) {
         Ok(data) => data,
@@ -276,7 +758,7 @@ fn update_service_info(service: &mut ServiceEntry) {
     service.message_time = i64::from_ne_bytes(message_time_b);
 
     // get and print read, write, open, close, & dup count, they are successive u64 bytes read from requests subscheme
-    let _ = read_helper(service, read_buffer, "request_count");
+    let requests_res = read_helper(service, read_buffer, "request_count");
 
     let mut read_bytes: [u8; 8] = [0; 8];
     let mut write_bytes: [u8; 8] = [0; 8];
@@ -298,89 +780,666 @@ fn update_service_info(service: &mut ServiceEntry) {
     service.error_count = u64::from_ne_bytes(error_bytes);
 
     // get and process the start time
-    let _ = read_helper(service, read_buffer, "time_stamp");
+    let time_res = read_helper(service, read_buffer, "time_stamp");
     let mut time_bytes = [0; 8];
     for i in 0..8 {
         time_bytes[i] = read_buffer[i];
     }
     let time_init_int = i64::from_ne_bytes(time_bytes);
     service.time_init = time_init_int;
+
+    if message_res.is_ok() && requests_res.is_ok() && time_res.is_ok() {
+        service.last_response_time = Local::now().timestamp_millis();
+        // a failed service that was left running is healthy again
+        if service.state == ServiceState::Failed {
+            info!("'{}' is responding again", service.config.name);
+            service.set_state(ServiceState::Running, EventKind::Health, "responding again");
+        }
+        true
+    } else {
+        false
+    }
+}
+
+/// Stops a service, refusing to do so while services that depend on it are running.
+///
+/// If `cascade` is set, running dependents are stopped first in reverse dependency order.
+/// The response lists every service that was stopped.
+fn stop_with_dependents(services: &mut HashMap<String, ServiceEntry>, name: &str, cascade: bool) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
+    let dependents = running_dependents(services, name);
+
+    if !dependents.is_empty() && !cascade {
+        warn!("stop failed: running services depend on '{}': {:?}", name, dependents);
+        return Err(Some(TOMLMessage::String(format!(
+            "Unable to stop '{}': running services depend on it: {}\nUse '--cascade' to stop them first",
+            name,
+            dependents.join(", ")
+        ))));
+    }
+
+    let mut steps: Vec<String> = Vec::new();
+    let mut affected: Vec<String> = Vec::new();
+    for target in dependents.iter().chain(std::iter::once(&name.to_string())) {
+        let step = stop(services.get_mut(target.as_str()).unwrap());
+        match step {
+            Ok(msg) => {
+                steps.push(message_text(&msg));
+                affected.push(target.clone());
+                refresh_entry(services, target);
+            }
+            Err(msg) => {
+                steps.push(message_text(&msg));
+                if target != name {
+                    steps.push(format!("Unable to stop '{}': dependent '{}' failed to stop", name, target));
+                }
+                if !affected.is_empty() {
+                    steps.push(format!("Stopped services: {}", affected.join(", ")));
+                }
+                return Err(Some(TOMLMessage::String(steps.join("\n"))));
+            }
+        }
+    }
+    if affected.len() > 1 {
+        steps.push(format!("Stopped services: {}", affected.join(", ")));
+    }
+    Ok(Some(TOMLMessage::String(steps.join("\n"))))
+}
+
+/// Returns the running services that depend on the service `name`, directly or not, in the order they should be stopped.
+fn running_dependents(services: &HashMap<String, ServiceEntry>, name: &str) -> Vec<String> {
+    deps::stop_order(services, name)
+        .into_iter()
+        .filter(|dependent| dependent != name && services[dependent.as_str()].has_process())
+        .collect()
+}
+
+/// Resolves the target `name` into the services it contains and the group of those services plus their
+/// dependencies, in dependency order.
+fn resolve_target(
+    services: &HashMap<String, ServiceEntry>,
+    targets: &HashMap<String, Target>,
+    name: &str,
+) -> std::result::Result<(Vec<String>, Vec<String>), Option<TOMLMessage>> {
+    deps::target_services(services, targets, name)
+        .and_then(|members| deps::group_start_order(services, &members).map(|group| (members, group)))
+        .map_err(|reason| {
+            warn!("unable to resolve target '@{}': {}", name, reason);
+            Some(TOMLMessage::String(format!("Unable to resolve '@{}': {}", name, reason)))
+        })
+}
+
+/// Starts every service in a target, along with their dependencies, with up to `max_parallel` of them starting
+/// at once. Each service waits until its dependencies are ready.
+///
+/// Services that are already running are left alone, and services that depend on one that fails to start are
+/// not started. The response lists each step in the order it finished.
+fn start_target(
+    services: &mut HashMap<String, ServiceEntry>,
+    targets: &HashMap<String, Target>,
+    name: &str,
+    max_parallel: usize,
+) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
+    let (_, group) = resolve_target(services, targets, name)?;
+
+    let results = start_parallel(services, &group, max_parallel);
+    if results.is_empty() {
+        return Ok(Some(TOMLMessage::String(format!("Every service in '@{}' is already running", name))));
+    }
+    let mut steps: Vec<String> = Vec::new();
+    let mut failed: Vec<String> = Vec::new();
+    for (service_name, result) in results {
+        match result {
+            Ok(msg) => steps.push(message_text(&msg)),
+            Err(msg) => {
+                steps.push(message_text(&msg));
+                failed.push(service_name);
+            }
+        }
+    }
+    if failed.is_empty() {
+        Ok(Some(TOMLMessage::String(steps.join("\n"))))
+    } else {
+        steps.push(format!("Unable to start '@{}': failed to start: {}", name, failed.join(", ")));
+        Err(Some(TOMLMessage::String(steps.join("\n"))))
+    }
+}
+
+/// Stops every service in a target in reverse dependency order, refusing to do so while services outside
+/// the target depend on them.
+///
+/// If `cascade` is set, those outside dependents are stopped first. Dependencies of the target's services
+/// are stopped as well, unless a service that is still running needs them. The response lists each step.
+fn stop_target(
+    services: &mut HashMap<String, ServiceEntry>,
+    targets: &HashMap<String, Target>,
+    name: &str,
+    cascade: bool,
+) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
+    let (members, group) = resolve_target(services, targets, name)?;
+
+    let mut dependents: Vec<String> = Vec::new();
+    for member in &members {
+        for dependent in deps::stop_order(services, member) {
+            if !group.contains(&dependent) && !dependents.contains(&dependent) && services[dependent.as_str()].has_process() {
+                dependents.push(dependent);
+            }
+        }
+    }
+    if !dependents.is_empty() && !cascade {
+        dependents.sort();
+        warn!("stop failed: running services depend on '@{}': {:?}", name, dependents);
+        return Err(Some(TOMLMessage::String(format!(
+            "Unable to stop '@{}': running services depend on it: {}\nUse '--cascade' to stop them first",
+            name,
+            dependents.join(", ")
+        ))));
+    }
+
+    let affected: Vec<String> = group.iter().chain(dependents.iter()).cloned().collect();
+    let mut order = deps::group_start_order(services, &affected).map_err(|reason| Some(TOMLMessage::String(reason)))?;
+    order.reverse();
+
+    let mut steps: Vec<String> = Vec::new();
+    for service_name in &order {
+        if !services[service_name.as_str()].has_process() {
+            continue;
+        }
+        // a dependency pulled in by the target stays up while something else still uses it
+        if !members.contains(service_name) && !dependents.contains(service_name) {
+            let mut users: Vec<String> = services
+                .values()
+                .filter(|service| service.has_process() && service.config.depends.contains(service_name))
+                .map(|service| service.config.name.clone())
+                .collect();
+            if !users.is_empty() {
+                users.sort();
+                steps.push(format!("Left '{}' running, it is needed by {}", service_name, users.join(", ")));
+                continue;
+            }
+        }
+        match stop(services.get_mut(service_name.as_str()).unwrap()) {
+            Ok(msg) => {
+                steps.push(message_text(&msg));
+                refresh_entry(services, service_name);
+            }
+            Err(msg) => {
+                steps.push(message_text(&msg));
+                steps.push(format!("Unable to stop '@{}': '{}' failed to stop", name, service_name));
+                return Err(Some(TOMLMessage::String(steps.join("\n"))));
+            }
+        }
+    }
+    if steps.is_empty() {
+        steps.push(format!("No service in '@{}' is running", name));
+    }
+    Ok(Some(TOMLMessage::String(steps.join("\n"))))
+}
+
+/// Re-reads the registry and applies its differences from the services in memory.
+/// The targets are replaced by those in the registry.
+///
+/// New services that autostart are started in dependency order. Removed services are dropped, or retired
+/// until they stop if they are running. Changed services take their new configuration right away if they
+/// are stopped, and the next time they start otherwise.
+fn reload(services: &mut HashMap<String, ServiceEntry>, targets: &mut HashMap<String, Target>) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
+    let mut registry = match read_registry_configs().and_then(|registry| Ok((registry, read_targets()?))) {
+        Ok((registry, new_targets)) => {
+            *targets = new_targets;
+            registry
+        }
+        Err(reason) => {
+            warn!("reload failed: {}", reason);
+            return Err(Some(TOMLMessage::String(format!("Unable to reload the registry: {}", reason))));
+        }
+    };
+    correct_entries(registry.values_mut());
+    let mut diff = RegistryDiff::default();
+
+    let mut removed: Vec<String> = services.keys().filter(|name| !registry.contains_key(*name)).cloned().collect();
+    removed.sort();
+    for name in removed {
+        let service = services.get_mut(&name).unwrap();
+        let action = if service.retired {
+            "Already waiting to be removed once it is stopped"
+        } else if service.has_process() {
+            service.retired = true;
+            "Will be removed once it is stopped"
+        } else {
+            services.remove(&name);
+            "Removed"
+        };
+        diff.removed.push(DiffEntry { name, fields: Vec::new(), action: action.to_string() });
+    }
+
+    let mut names: Vec<String> = registry.keys().cloned().collect();
+    names.sort();
+    let mut added: Vec<String> = Vec::new();
+    for name in names {
+        let config = registry.remove(&name).unwrap();
+        let Some(service) = services.get_mut(&name) else {
+            services.insert(name.clone(), ServiceEntry::new(config));
+            added.push(name);
+            continue;
+        };
+        // compare against the configuration the service will use next
+        let fields = changed_fields(service.pending_config.as_ref().unwrap_or(&service.config), &config);
+        let action = if !fields.is_empty() && service.has_process() {
+            if config == service.config {
+                service.pending_config = None;
+                "Back to the configuration it is running with".to_string()
+            } else {
+                service.pending_config = Some(config);
+                "Restart needed to apply".to_string()
+            }
+        } else if !fields.is_empty() {
+            service.config = config;
+            service.pending_config = None;
+            "Applied".to_string()
+        } else if service.retired {
+            "Back in the registry, it will no longer be removed".to_string()
+        } else {
+            continue;
+        };
+        service.retired = false;
+        info!("reloaded '{}': {}", name, action);
+        diff.changed.push(DiffEntry { name, fields, action });
+    }
+
+    // start new services in dependency order
+    let plan = deps::boot_plan(services);
+    let mut actions: HashMap<String, String> = HashMap::new();
+    for (name, reason) in plan.rejected {
+        actions.insert(name, format!("Not started: {}", reason));
+    }
+    for name in plan.order.iter().filter(|name| added.contains(*name)) {
+        let missing: Vec<String> = services[name]
+            .config
+            .depends
+            .iter()
+            .filter(|dep| !services.get(*dep).is_some_and(|dep| dep.has_process()))
+            .cloned()
+            .collect();
+        let service = services.get_mut(name).unwrap();
+        let action = if !service.config.autostart {
+            String::from("Added, not started since it does not autostart")
+        } else if !missing.is_empty() {
+            format!("Not started: dependencies not running: {}", missing.join(", "))
+        } else {
+            match start(service) {
+                Ok(msg) | Err(msg) => message_text(&msg),
+            }
+        };
+        actions.insert(name.clone(), action);
+    }
+    for name in added {
+        let action = actions.remove(&name).unwrap_or_else(|| String::from("Added"));
+        info!("reloaded '{}': {}", name, action);
+        diff.added.push(DiffEntry { name, fields: Vec::new(), action });
+    }
+
+    Ok(Some(TOMLMessage::RegistryDiff(diff)))
+}
+
+/// Updates the configuration of a stopped service from the on-disk registry,
+/// removing it if it is no longer present in the registry.
+///
+/// The rest of the entry is kept so the service's accumulated stats survive the refresh. The entry gets the same
+/// corrections as at boot and on reload, which an entry with `manual_override` set only has in memory.
+fn refresh_entry(services: &mut HashMap<String, ServiceEntry>, name: &str) {
+    let mut registry = read_registry();
+    match registry.remove(name) {
+        Some(mut s) => {
+            correct_entries(std::iter::once(&mut s.config));
+            if let Some(service) = services.get_mut(name) {
+                service.config = s.config;
+                service.pending_config = None;
+            } else {
+                services.insert(name.to_string(), s);
+            }
+        }
+        None => {
+            services.remove(name);
+        }
+    }
 }
 
 /// Stops a service.
 fn stop(service: &mut ServiceEntry) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
-    if service.running {
-        let _ = clear(service);
-        info!("trying to kill pid {}", service.pid);
-        let _kill_ret = syscall::call::kill(service.pid, syscall::SIGKILL);
-        service.running = false;
+    if service.restart_deadline != 0 {
+        // the service is being restarted and is not ready yet, so there is nothing to shut down gracefully
+        kill_launched(service);
+        service.set_state(ServiceState::Stopped, EventKind::Stop, "restart cancelled");
+        info!("cancelled restart of '{}'", service.config.name);
+        Ok(Some(TOMLMessage::String(format!("Cancelled restart of '{}'", service.config.name))))
+    } else if service.has_process() {
+        if !service.config.is_managed() && service.pid == 0 {
+            warn!("stop failed: '{}' daemonized, so its pid is unknown", service.config.name);
+            return Err(Some(TOMLMessage::String(format!(
+                "Unable to stop '{}': The service daemonized, so its pid is unknown",
+                service.config.name
+            ))));
+        }
+        if service.config.is_managed() {
+            let _ = clear(service);
+        }
+        service.set_state(ServiceState::Stopping, EventKind::Stop, "stop requested");
+        let step = shutdown(service);
+        service.set_state(ServiceState::Stopped, EventKind::Stop, &step.to_string());
         
         // dev note: eval_cmd match statement will remove service from internal list if it does not exist in the registry anymore
         let name = service.config.name.clone();
-        Ok(Some(TOMLMessage::String(format!("Stopped service '{}'", name))))
+        Ok(Some(TOMLMessage::String(format!("Stopped service '{}' ({})", name, step))))
+    } else if service.next_retry_time != 0 {
+        // the service failed and is waiting to be restarted, so just cancel the restart
+        service.next_retry_time = 0;
+        service.set_state(ServiceState::Stopped, EventKind::Stop, "pending restart cancelled");
+        info!("cancelled pending restart of '{}'", service.config.name);
+        Ok(Some(TOMLMessage::String(format!("Cancelled pending restart of '{}'", service.config.name))))
     } else {
         warn!("stop failed: '{}' was already stopped", service.config.name);
         Err(Some(TOMLMessage::String(format!("Unable to stop '{}': Already stopped", service.config.name))))
     }
 }
 
+/// The step of the stop protocol that made a service exit.
+enum StopStep {
+    /// The service shut down after being asked to through its control subscheme.
+    Requested,
+    /// The service exited after being sent SIGTERM.
+    Terminated,
+    /// The service was sent SIGKILL.
+    Killed,
+}
+
+impl std::fmt::Display for StopStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopStep::Requested => write!(f, "shut down on request"),
+            StopStep::Terminated => write!(f, "terminated by SIGTERM"),
+            StopStep::Killed => write!(f, "killed by SIGKILL"),
+        }
+    }
+}
+
+/// How often [wait_for_exit] checks whether a process is still alive.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Makes a running service's process exit, escalating until it does.
+///
+/// A managed service is first asked to stop through its control subscheme and given `stop_timeout_ms`
+/// to call `ManagedScheme::shutdown()` and exit. If it is still alive it is sent SIGTERM,
+/// and if it has not exited within `term_timeout_ms` after that, SIGKILL.
+fn shutdown(service: &mut ServiceEntry) -> StopStep {
+    let name = service.config.name.clone();
+    let pid = service.pid;
+    let step = shutdown_steps(service, &name, pid);
+    service.worker = None;
+    // reap a foreground process so it does not linger as a zombie
+    if let Some(mut child) = service.child.take() {
+        let _ = child.wait();
+    }
+    step
+}
+
+/// Runs the steps of [shutdown] until the service's process exits.
+fn shutdown_steps(service: &mut ServiceEntry, name: &str, pid: usize) -> StopStep {
+    if service.config.is_managed() {
+        if request_stop(service) && wait_for_exit(service, service.config.stop_timeout_ms) {
+            info!("'{}' shut down on request", name);
+            return StopStep::Requested;
+        }
+        warn!("'{}' did not shut down within {} ms of being asked to stop", name, service.config.stop_timeout_ms);
+    }
+
+    info!("sending SIGTERM to '{}' (pid {})", name, pid);
+    let _term_ret = syscall::kill(pid, syscall::SIGTERM);
+    if wait_for_exit(service, service.config.term_timeout_ms) {
+        return StopStep::Terminated;
+    }
+    warn!("'{}' did not exit within {} ms of SIGTERM, sending SIGKILL", name, service.config.term_timeout_ms);
+    let _kill_ret = syscall::kill(pid, syscall::SIGKILL);
+    StopStep::Killed
+}
+
+/// Writes "stop" to a service's control subscheme.
+///
+/// Unlike [write_helper], a timeout here does not try to recover the service, since it is being stopped anyway.
+fn request_stop(service: &mut ServiceEntry) -> bool {
+    let timeout = Duration::from_millis(service.config.timeout_ms);
+    match service.worker().write("control", b"stop", timeout) {
+        Some(result) => result.is_ok(),
+        None => {
+            service.worker = None;
+            false
+        }
+    }
+}
+
+/// Waits up to `timeout_ms` milliseconds for a service's process to exit. Returns true if it did.
+fn wait_for_exit(service: &mut ServiceEntry, timeout_ms: u64) -> bool {
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    loop {
+        // a child of the service monitor stays a zombie until it is waited on, so check it directly
+        if let Some(child) = service.child.as_mut() {
+            if !matches!(child.try_wait(), Ok(None)) {
+                return true;
+            }
+        } else if let Err(err) = syscall::kill(service.pid, 0) {
+            if err.errno == syscall::ESRCH {
+                return true;
+            }
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(EXIT_POLL_INTERVAL);
+    }
+}
+
+/// Starts a service once all of its dependencies are running.
+///
+/// If any dependency is not running, the start is refused and the missing dependencies are reported,
+/// unless `force` is set, in which case the missing dependencies are started first in dependency order
+/// and each step is reported.
+fn start_with_deps(services: &mut HashMap<String, ServiceEntry>, name: &str, force: bool) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
+    if services.get(name).is_some_and(|service| service.has_process()) {
+        return start(services.get_mut(name).unwrap());
+    }
+
+    let chain = match deps::dependency_chain(services, name) {
+        Ok(chain) => chain,
+        Err(reason) => {
+            warn!("start failed: '{}' has unresolvable dependencies: {}", name, reason);
+            return Err(Some(TOMLMessage::String(format!("Unable to start '{}': {}", name, reason))));
+        }
+    };
+    let missing: Vec<String> = chain
+        .iter()
+        .filter(|dep| dep.as_str() != name && !services[dep.as_str()].is_running())
+        .cloned()
+        .collect();
+
+    if missing.is_empty() {
+        return start(services.get_mut(name).unwrap());
+    }
+    if !force {
+        warn!("start failed: dependencies of '{}' are not running: {:?}", name, missing);
+        return Err(Some(TOMLMessage::String(format!(
+            "Unable to start '{}': dependencies not running: {}\nUse '--force' to start them first",
+            name,
+            missing.join(", ")
+        ))));
+    }
+
+    let mut steps: Vec<String> = Vec::new();
+    for dep in missing.iter().chain(std::iter::once(&name.to_string())) {
+        let step = start(services.get_mut(dep.as_str()).unwrap());
+        match step {
+            Ok(msg) => steps.push(message_text(&msg)),
+            Err(msg) => {
+                steps.push(message_text(&msg));
+                if dep != name {
+                    steps.push(format!("Unable to start '{}': dependency '{}' failed to start", name, dep));
+                }
+                return Err(Some(TOMLMessage::String(steps.join("\n"))));
+            }
+        }
+    }
+    Ok(Some(TOMLMessage::String(steps.join("\n"))))
+}
+
+/// The most state, in bytes, a service can export through its "state" subscheme. A service whose state is
+/// larger fails the read with `EOVERFLOW`, so it is not restarted with `--restore`.
+const MAX_STATE_SIZE: usize = 4096;
+
+/// Stops a service gracefully and starts it again with the same configuration, keeping its long-term totals.
+///
+/// If `restore` is set, the state the service exports through its "state" subscheme is read before it is
+/// stopped and written back once it has started again. The response reports how long each phase took.
+///
+/// A service that running services depend on is not restarted, since they would lose it while it is down.
+fn restart_service(services: &mut HashMap<String, ServiceEntry>, name: &str, restore: bool) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
+    let dependents = running_dependents(services, name);
+    let service = services.get_mut(name).unwrap();
+    if restore && !service.config.is_managed() {
+        warn!("restart failed: '{}' is unmanaged and cannot restore state", name);
+        return Err(Some(TOMLMessage::String(format!("Unable to restart '{}': Unmanaged services have no state to restore", name))));
+    }
+    if service.has_process() && !dependents.is_empty() {
+        warn!("restart failed: running services depend on '{}': {:?}", name, dependents);
+        return Err(Some(TOMLMessage::String(format!(
+            "Unable to restart '{}': running services depend on it: {}\nStop them first",
+            name,
+            dependents.join(", ")
+        ))));
+    }
+    let mut steps: Vec<String> = Vec::new();
+
+    let mut state: Vec<u8> = Vec::new();
+    if restore && service.has_process() {
+        let phase = Instant::now();
+        let read_buffer: &mut [u8] = &mut [0; MAX_STATE_SIZE];
+        // read through the worker directly, since a timeout must abort the restart rather than recover the service
+        let timeout = Duration::from_millis(service.config.timeout_ms);
+        let result = match service.worker().read("state", read_buffer, timeout) {
+            Some(Ok(size)) => Ok(size),
+            Some(Err(e)) if e.errno() == EOVERFLOW => Err(format!("its state is larger than {} bytes", MAX_STATE_SIZE)),
+            Some(Err(e)) => Err(e.to_string()),
+            None => {
+                service.worker = None;
+                let reason = format!("read of 'state' took longer than {} ms", service.config.timeout_ms);
+                service.record_event(EventKind::Timeout, &reason);
+                Err(reason)
+            }
+        };
+        match result {
+            Ok(size) => {
+                state = read_buffer[..size].to_vec();
+                steps.push(format!("Checkpointed {} bytes of state in {} ms", size, phase.elapsed().as_millis()));
+            }
+            Err(e) => {
+                warn!("restart failed: could not read state from '{}': {}", name, e);
+                return Err(Some(TOMLMessage::String(format!("Unable to restart '{}': Failed to read its state: {}", name, e))));
+            }
+        }
+    }
+
+    if service.has_process() {
+        let phase = Instant::now();
+        match stop(service) {
+            Ok(msg) => steps.push(format!("{} in {} ms", message_text(&msg), phase.elapsed().as_millis())),
+            Err(msg) => {
+                steps.push(message_text(&msg));
+                return Err(Some(TOMLMessage::String(steps.join("\n"))));
+            }
+        }
+    } else {
+        steps.push(format!("'{}' was not running", name));
+    }
+
+    let phase = Instant::now();
+    match start(service) {
+        Ok(msg) => steps.push(format!("{} in {} ms", message_text(&msg), phase.elapsed().as_millis())),
+        Err(msg) => {
+            steps.push(message_text(&msg));
+            return Err(Some(TOMLMessage::String(steps.join("\n"))));
+        }
+    }
+
+    if restore && !state.is_empty() {
+        let phase = Instant::now();
+        match write_helper(service, "state", &state) {
+            Ok(_) => steps.push(format!("Restored {} bytes of state in {} ms", state.len(), phase.elapsed().as_millis())),
+            Err(e) => {
+                warn!("could not restore state of '{}': {}", name, e);
+                steps.push(format!("Unable to restore the state of '{}': {}", name, e));
+                return Err(Some(TOMLMessage::String(steps.join("\n"))));
+            }
+        }
+    }
+    Ok(Some(TOMLMessage::String(steps.join("\n"))))
+}
+
+/// Returns the text of a command result's message, or an empty string if it has none.
+fn message_text(message: &Option<TOMLMessage>) -> String {
+    match message {
+        Some(TOMLMessage::String(text)) => text.clone(),
+        _ => String::new(),
+    }
+}
+
 /// Starts a service.
 fn start(service: &mut ServiceEntry) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
-    // can add args here later with '.arg()'
-    if !service.running {
-        match std::process::Command::new(service.config.name.as_str()).spawn() {
+    if !service.has_process() {
+        service.apply_pending_config();
+        service.set_state(ServiceState::Starting, EventKind::Start, "start requested");
+        let launched = Instant::now();
+        if !service.config.is_managed() {
+            service.time_started = Local::now().timestamp_millis();
+            return match launch_unmanaged(service).and_then(|()| await_ready(service, launched)) {
+                Ok(()) => {
+                    service.set_state(ServiceState::Running, EventKind::Start, "started");
+                    service.next_retry_time = 0;
+                    service.restart_times.clear();
+                    Ok(Some(TOMLMessage::String(format!("Started '{}' with pid {:#?}", service.config.name, service.pid))))
+                }
+                Err(reason) => {
+                    service.set_state(ServiceState::Failed, EventKind::Start, &reason);
+                    warn!("start failed: could not start {}: {}", service.config.name, reason);
+                    Err(Some(TOMLMessage::String(format!("Unable to start '{}': {}", service.config.name, reason))))
+                }
+            };
+        }
+        match service.config.command().and_then(|mut command| command.spawn()) {
             Ok(mut child) => {
                 //service.pid = child.id().try_into().unwrap();
                 //service.pid += 2;
                 service.time_started = Local::now().timestamp_millis(); // where should this go for the start command?
                 // wait for the daemon loader to exit so we can safely get the pid
-                match child.wait() {
-                    Ok(_ext) => {}
-                    Err(_) => {
-                        error!("{} failed to start!", service.config.name);
-                        return Err(Some(TOMLMessage::String(format!("Unable to start '{}': Process exited with failure code", service.config.name))));
-                    }
+                if let Err(reason) = wait_for_launcher(service, &mut child, launched) {
+                    service.set_state(ServiceState::Failed, EventKind::Start, &reason);
+                    error!("{} failed to start: {}", service.config.name, reason);
+                    return Err(Some(TOMLMessage::String(format!("Unable to start '{}': {}", service.config.name, reason))));
                 }
-                let child_scheme =
-                    match libredox::call::open(service.config.scheme_path.clone(), O_RDWR, 1) {
-                        Ok(fd) => fd,
-                        Err(_) => {
-                            error!("failed to open service scheme!");
-                            return Err(Some(TOMLMessage::String(format!("Unable to start '{}': Failed to open scheme at '{}'", service.config.name, service.config.scheme_path))));
-                        }
-                    };
-                let pid_scheme = match libredox::call::dup(child_scheme, b"pid") {
-                    Ok(pid_fd) => pid_fd,
-                    Err(_) => {
-                        error!("failed to dup service pid scheme!");
-                        return Err(Some(TOMLMessage::String(format!("Unable to start '{}': Failed to dup service pid scheme", service.config.name))));
-                    }
-                };
-                let read_buffer: &mut [u8] = &mut [b'0'; 32];
-                match libredox::call::read(pid_scheme, read_buffer) {
-                    Ok(_usize) => {}
-                    Err(_) => {
-                        error!("could not read pid from service!");
-                        return Err(Some(TOMLMessage::String(format!("Unable to start '{}': Failed to read pid from service", service.config.name))));
-                    }
-                }
-                // process the buffer based on the request
-                let mut pid_bytes: [u8; 8] = [0; 8];
-                for i in 0..8 {
-                    //info!("byte {} reads {}", i, read_buffer[i]);
-                    pid_bytes[i] = read_buffer[i];
+                if let Err(reason) = await_ready(service, launched) {
+                    service.set_state(ServiceState::Failed, EventKind::Start, &reason);
+                    error!("{} did not become ready: {}", service.config.name, reason);
+                    return Err(Some(TOMLMessage::String(format!("Unable to start '{}': {}", service.config.name, reason))));
                 }
-                let pid = usize::from_ne_bytes(pid_bytes);
-                service.pid = pid;
                 info!("child started with pid: {:#?}", service.pid);
-                service.running = true;
+                let reason = format!("started with pid {}, ready after {} ms", service.pid, service.time_ready - service.time_started);
+                service.set_state(ServiceState::Running, EventKind::Start, &reason);
+                // a manual start gives the service a fresh restart history
+                service.next_retry_time = 0;
+                service.restart_times.clear();
 
                 Ok(Some(TOMLMessage::String(format!("Started '{}' with pid {:#?}", service.config.name, service.pid))))
             }
 
-            Err(_e) => {
-                warn!("start failed: could not start {}", service.config.name);
-                Err(Some(TOMLMessage::String(format!("Unable to start '{}': Failed to locate executable", service.config.name))))
+            Err(e) => {
+                service.set_state(ServiceState::Failed, EventKind::Start, &format!("failed to launch: {}", e));
+                warn!("start failed: could not start {}: {}", service.config.name, e);
+                Err(Some(TOMLMessage::String(format!("Unable to start '{}': Failed to launch '{}': {}", service.config.name, service.config.executable(), e))))
             }
         }
     } else {
@@ -401,16 +1460,174 @@ fn start(service: &mut ServiceEntry) -> Result<Option<TOMLMessage>, Option<TOMLM
     }
 }
 
+/// How often a service that was just launched is checked for readiness.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Waits for the launcher of a managed service that was launched at `launched` to exit, which it does once the
+/// daemon has forked into the background.
+///
+/// A launcher that is still running after the service's `start_timeout_ms` is killed, a timeout event is recorded,
+/// and an [Err] describing why is returned.
+fn wait_for_launcher(service: &mut ServiceEntry, child: &mut std::process::Child, launched: Instant) -> std::result::Result<(), String> {
+    let deadline = launched + Duration::from_millis(service.config.start_timeout_ms);
+    loop {
+        match child.try_wait() {
+            Ok(Some(_status)) => return Ok(()),
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                let reason = format!("Launcher did not finish within {} ms", service.config.start_timeout_ms);
+                service.record_event(EventKind::Timeout, &reason);
+                return Err(reason);
+            }
+            Ok(None) => thread::sleep(EXIT_POLL_INTERVAL),
+            Err(e) => return Err(format!("Failed to wait for the launcher: {}", e)),
+        }
+    }
+}
+
+/// Waits for a service that was launched at `launched` to pass its `ready_check` and, if it is managed, reads its pid.
+///
+/// A service that is not ready within its `start_timeout_ms` is stopped so it is not left half-started,
+/// and an [Err] describing why is returned.
+fn await_ready(service: &mut ServiceEntry, launched: Instant) -> std::result::Result<(), String> {
+    reset_for_launch(service);
+    let deadline = launched + Duration::from_millis(service.config.start_timeout_ms);
+    let mut result = loop {
+        let reason = match check_ready(service) {
+            Ok(true) => break Ok(()),
+            Ok(false) => String::from("the service has not reported that it is ready"),
+            Err(reason) => reason,
+        };
+        if Instant::now() >= deadline {
+            break Err(format!("Not ready within {} ms: {}", service.config.start_timeout_ms, reason));
+        }
+        thread::sleep(READY_POLL_INTERVAL);
+    };
+    if result.is_ok() && service.config.is_managed() {
+        result = read_pid(service).map(|pid| service.pid = pid);
+    }
+    match result {
+        Ok(()) => service.time_ready = Local::now().timestamp_millis(),
+        Err(_) => abandon_start(service),
+    }
+    result
+}
+
+/// Forgets what was known about a service's previous process before a new one is launched.
+fn reset_for_launch(service: &mut ServiceEntry) {
+    // the process is new, so nothing opened on the previous one is any use
+    service.worker = None;
+    service.fold_counts();
+    if service.config.is_managed() {
+        service.pid = 0;
+    }
+    service.time_ready = 0;
+    service.probe_failures = 0;
+    service.health = HealthStatus::Unknown;
+    service.health_output.clear();
+    service.health_time = 0;
+}
+
+/// Checks once whether a service that was just launched passes its `ready_check`.
+/// Returns an [Err] describing the problem if the check could not be made.
+fn check_ready(service: &mut ServiceEntry) -> std::result::Result<bool, String> {
+    // an unmanaged service can only be checked through its scheme, if it has one
+    if !service.config.is_managed() && (service.config.ready_check != ReadyCheck::Scheme || service.config.scheme_path.is_empty()) {
+        return Ok(true);
+    }
+    let timeout = Duration::from_millis(service.config.timeout_ms);
+    let subscheme = match service.config.ready_check {
+        ReadyCheck::Scheme => {
+            return match service.worker().open(timeout) {
+                Some(Ok(())) => Ok(true),
+                Some(Err(e)) => Err(format!("Failed to open '{}': {}", service.config.scheme_path, e)),
+                None => {
+                    service.worker = None;
+                    Err(format!("Timed out after {} ms opening '{}'", service.config.timeout_ms, service.config.scheme_path))
+                }
+            };
+        }
+        ReadyCheck::Pid => "pid",
+        ReadyCheck::Ready => "ready",
+    };
+    let read_buffer: &mut [u8; 8] = &mut [0; 8];
+    match service.worker().read(subscheme, read_buffer, timeout) {
+        // any pid will do, the others have to be set
+        Some(Ok(_size)) => Ok(subscheme == "pid" || u64::from_ne_bytes(*read_buffer) != 0),
+        Some(Err(e)) => Err(format!("Failed to read {} from '{}': {}", subscheme, service.config.scheme_path, e)),
+        None => {
+            service.worker = None;
+            Err(format!("Timed out after {} ms reading {} from '{}'", service.config.timeout_ms, subscheme, service.config.scheme_path))
+        }
+    }
+}
+
+/// Stops a service that was launched but never became ready, if its process can be found.
+fn abandon_start(service: &mut ServiceEntry) {
+    if service.config.is_managed() {
+        if let Ok(pid) = read_pid(service) {
+            service.pid = pid;
+        }
+    }
+    if service.pid != 0 {
+        let step = shutdown(service);
+        info!("'{}' was {} after failing to become ready", service.config.name, step);
+    } else if service.config.is_managed() && request_stop(service) {
+        info!("asked '{}' to stop after it failed to become ready", service.config.name);
+    } else {
+        warn!("'{}' failed to become ready and may still be running, its pid is unknown", service.config.name);
+    }
+    service.worker = None;
+}
+
+/// Launches an unmanaged service, which is tracked only through the process that was spawned.
+///
+/// This doesn't wait to find out whether the process daemonizes. [check_exit] decides that on the supervision
+/// ticks: if the process exits successfully within the service's `start_timeout_ms` and its scheme can be opened,
+/// it completed the daemon handshake and forked into the background, and the daemon's own pid is unknown.
+/// If it is still running after that, it is a foreground process and stays tracked by its pid.
+fn launch_unmanaged(service: &mut ServiceEntry) -> std::result::Result<(), String> {
+    let child = service
+        .config
+        .command()
+        .and_then(|mut command| command.spawn())
+        .map_err(|e| format!("Failed to launch '{}': {}", service.config.executable(), e))?;
+    service.time_init = Local::now().timestamp_millis();
+    service.daemonize_deadline = service.time_init + service.config.start_timeout_ms as i64;
+    service.pid = child.id() as usize;
+    service.child = Some(child);
+    Ok(())
+}
+
+/// Returns true if an unmanaged service's scheme can be opened, meaning a daemon is serving it.
+fn scheme_is_served(service: &ServiceEntry) -> bool {
+    if service.config.scheme_path.is_empty() {
+        return false;
+    }
+    match libredox::call::open(service.config.scheme_path.clone(), O_RDONLY, 0) {
+        Ok(fd) => {
+            let _ = libredox::call::close(fd);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
 /// Collects runtime info about a service to be viewed by a user-facing frontend.
 fn info(service: &mut ServiceEntry) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
-    let stats = if service.running {
-        update_service_info(service);
+    let stats = if service.has_process() {
+        // a failed monitor-only service may be hung, so it shows what supervise last read from it
+        if service.is_running() && service.config.is_managed() {
+            let _ = update_service_info(service);
+        }
 
         ServiceDetailStats {
             name: service.config.name.clone(),
             pid: service.pid,
             time_init: service.time_init,
             time_started: service.time_started,
+            time_ready: service.time_ready,
             time_now: Local::now().timestamp_millis(),
             read_count: service.read_count,
             total_reads: service.total_reads + service.read_count,
@@ -426,7 +1643,17 @@ fn info(service: &mut ServiceEntry) -> Result<Option<TOMLMessage>, Option<TOMLMe
             total_errors: service.total_errors + service.error_count,
             message: service.message.clone(),
             message_time: service.message_time,
-            running: service.running,
+            last_response_time: service.last_response_time,
+            restart_count: service.restart_count,
+            next_retry_time: service.next_retry_time,
+            last_exit: service.last_exit.as_ref().map(|exit| exit.to_string()).unwrap_or_default(),
+            last_exit_time: service.last_exit.as_ref().map(|exit| exit.time).unwrap_or(0),
+            running: service.has_process(),
+            state: service.state,
+            needs_restart: service.pending_config.is_some(),
+            health: service.health,
+            health_output: service.health_output.clone(),
+            health_time: service.health_time,
         }
     } else {
         ServiceDetailStats {
@@ -434,6 +1661,7 @@ fn info(service: &mut ServiceEntry) -> Result<Option<TOMLMessage>, Option<TOMLMe
             pid: service.pid,
             time_init: service.time_init,
             time_started: service.time_started,
+            time_ready: service.time_ready,
             time_now: Local::now().timestamp_millis(),
             read_count: 0,
             total_reads: service.total_reads + service.read_count,
@@ -449,7 +1677,17 @@ fn info(service: &mut ServiceEntry) -> Result<Option<TOMLMessage>, Option<TOMLMe
             total_errors: service.total_errors + service.error_count,
             message: service.message.clone(),
             message_time: service.message_time,
-            running: service.running,
+            last_response_time: service.last_response_time,
+            restart_count: service.restart_count,
+            next_retry_time: service.next_retry_time,
+            last_exit: service.last_exit.as_ref().map(|exit| exit.to_string()).unwrap_or_default(),
+            last_exit_time: service.last_exit.as_ref().map(|exit| exit.time).unwrap_or(0),
+            running: service.has_process(),
+            state: service.state,
+            needs_restart: service.pending_config.is_some(),
+            health: service.health,
+            health_output: service.health_output.clone(),
+            health_time: service.health_time,
         }
     };
     Ok(Some(TOMLMessage::ServiceDetail(stats)))
@@ -461,12 +1699,18 @@ fn info(service: &mut ServiceEntry) -> Result<Option<TOMLMessage>, Option<TOMLMe
 /// or removed from the registry while it is still running. In these cases, the info shown
 /// will reflect the configuration of the servce it was launched with. This will update
 /// after the service is stopped.
-fn list(service_map: &mut HashMap<String, ServiceEntry>) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
+///
+/// If `only` is given, only the services it names are listed.
+fn list(service_map: &mut HashMap<String, ServiceEntry>, only: Option<&[String]>) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
     let mut service_stats: Vec<ServiceRuntimeStats> = Vec::new();
 
     for service in service_map.values_mut() {
-        if service.running {
-            update_service_info(service);
+        if only.is_some_and(|names| !names.contains(&service.config.name)) {
+            continue;
+        }
+        // a failed monitor-only service may be hung, so it shows what supervise last read from it
+        if service.is_running() && service.config.is_managed() {
+            let _ = update_service_info(service);
         }
 
         service_stats.push(ServiceRuntimeStats {
@@ -476,7 +1720,10 @@ fn list(service_map: &mut HashMap<String, ServiceEntry>) -> Result<Option<TOMLMe
             time_started: service.time_started,
             time_now: Local::now().timestamp_millis(),
             message: service.message.clone(),
-            running: service.running,
+            running: service.has_process(),
+            state: service.state,
+            service_type: service.config.r#type.clone(),
+            needs_restart: service.pending_config.is_some(),
         });
     }
 
@@ -485,7 +1732,10 @@ fn list(service_map: &mut HashMap<String, ServiceEntry>) -> Result<Option<TOMLMe
 
 /// Clears the short-term runtime stats for a service.
 fn clear(service: &mut ServiceEntry) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
-    if service.running {
+    if !service.config.is_managed() {
+        warn!("Attempted to clear '{}' which is unmanaged!", service.config.name);
+        Err(Some(TOMLMessage::String(format!("Failed to clear '{}'; unmanaged services have no stats", service.config.name))))
+    } else if service.is_running() {
         // read the requests into a buffer
         let read_buffer: &mut [u8] = &mut [b'0'; 48];
         let _ = read_helper(service, read_buffer, "request_count");
@@ -503,23 +1753,21 @@ fn clear(service: &mut ServiceEntry) -> Result<Option<TOMLMessage>, Option<TOMLM
         close_bytes.clone_from_slice(&read_buffer[24..32]);
         dup_bytes.clone_from_slice(&read_buffer[32..40]);
         error_bytes.clone_from_slice(&read_buffer[40..48]);
+        service.read_count = u64::from_ne_bytes(read_bytes);
+        service.write_count = u64::from_ne_bytes(write_bytes);
+        service.open_count = u64::from_ne_bytes(open_bytes);
+        service.close_count = u64::from_ne_bytes(close_bytes);
+        service.dup_count = u64::from_ne_bytes(dup_bytes);
+        service.error_count = u64::from_ne_bytes(error_bytes);
         // count this for our service's totals
-        service.total_reads += u64::from_ne_bytes(read_bytes);
-        service.total_writes += u64::from_ne_bytes(write_bytes);
-        service.total_opens += u64::from_ne_bytes(open_bytes);
-        service.total_closes += u64::from_ne_bytes(close_bytes);
-        service.total_dups += u64::from_ne_bytes(dup_bytes);
-        service.total_errors += u64::from_ne_bytes(error_bytes);
+        service.fold_counts();
 
         // clear the data and close the schemes.
-        let _ = write_helper(service, "control", "clear");
-        
-        service.read_count = 0;
-        service.write_count = 0;
-        service.open_count = 0;
-        service.close_count = 0;
-        service.dup_count = 0;
-        service.error_count = 0;
+        let _ = write_helper(service, "control", b"clear");
+        // clearing a restarted service acknowledges the restart
+        if service.state == ServiceState::Restarted {
+            service.set_state(ServiceState::Running, EventKind::Clear, "restart acknowledged");
+        }
 
         Ok(Some(TOMLMessage::String(format!("Cleared short-term stats for '{}'", service.config.name))))
     } else {
@@ -537,9 +1785,9 @@ fn test_timeout(gtrand2: &mut ServiceEntry) {
         "read random {:#?} from gtrand2, forcing timeout...",
         i64::from_ne_bytes(*read_buf)
     );
-    let _ = write_helper(gtrand2, "", "timeout");
+    let _ = write_helper(gtrand2, "", b"timeout");
     // expecting this call to time out
-    match write_helper(gtrand2, "", "rseed") {
+    match write_helper(gtrand2, "", b"rseed") {
         Ok(_usize) => {
             info!("wrote new seed to gtrand2 after recovering from timeout!");
         }
@@ -548,7 +1796,7 @@ fn test_timeout(gtrand2: &mut ServiceEntry) {
         }
     }
 
-    let _ = write_helper(gtrand2, "", "timeout");
+    let _ = write_helper(gtrand2, "", b"timeout");
     match read_helper(gtrand2, read_buf, "") {
         Ok(_usize) => {
             info!(
@@ -570,14 +1818,14 @@ fn test_count_ops(service: &mut ServiceEntry) -> i64 {
         "successfully read random {:#?}",
         i64::from_ne_bytes(*read_buf)
     );
-    let _ = write_helper(service, "", "");
+    let _ = write_helper(service, "", b"");
     return i64::from_ne_bytes(*read_buf);
 }
 
 // todo: remove (unused)
 fn test_err(gtrand2: &mut ServiceEntry) {
     let timeout_req = "error";
-    let _ = write_helper(gtrand2, "", timeout_req);
+    let _ = write_helper(gtrand2, "", timeout_req.as_bytes());
     let read_buf = &mut [b'0'; 32];
     // for now we expect this to hang,
     match read_helper(gtrand2, read_buf, "") {
@@ -593,223 +1841,371 @@ fn test_err(gtrand2: &mut ServiceEntry) {
 }
 
 /// Function to help read from a service's scheme.
+///
+/// The read is done by the service's worker and may take up to the service's `timeout_ms`. If it times out,
+/// the service is recovered and the read fails.
 fn read_helper(service: &mut ServiceEntry, read_buf: &mut [u8], data: &str) -> Result<usize> {
-    let mut try_again = true;
-    let mut result: Result<usize> = Err(Error::new(EBADF));
-    while try_again {
-        result = match libredox::call::open(service.config.scheme_path.clone(), O_RDWR, 0) {
-            Ok(child_scheme) => {
-                // determine which scheme we are trying to read from
-                let read_scheme = if !data.is_empty() {
-                    let data_scheme = libredox::call::dup(child_scheme, data.as_bytes())?;
-                    let _close_res = libredox::call::close(child_scheme);
-                    data_scheme
-                } else {
-                    child_scheme
-                };
+    let timeout = Duration::from_millis(service.config.timeout_ms);
+    match service.worker().read(data, read_buf, timeout) {
+        Some(result) => result,
+        None => {
+            warn!("read operation on {} timed out!", service.config.name);
+            let reason = format!("read of '{}' took longer than {} ms", data, service.config.timeout_ms);
+            service.record_event(EventKind::Timeout, &reason);
+            recover(service);
+            Err(Error::new(EBADF))
+        }
+    }
+}
 
-                // read from the scheme with a timeout
-                let (sender, receiver) = mpsc::channel::<Result<usize>>();
-                let read_thread = thread::spawn(move || {
-                    let thread_buf: &mut [u8; 64] = &mut [0; 64];
-                    match sender.send(libredox::call::read(read_scheme, thread_buf)) {
-                        Ok(_result) => {
-                            return *thread_buf;
-                        }
+/// Function to help write to a service's scheme.
+///
+/// The write is done by the service's worker and may take up to the service's `timeout_ms`. If it times out,
+/// the service is recovered and the write fails.
+fn write_helper(service: &mut ServiceEntry, subscheme_name: &str, data: &[u8]) -> Result<usize> {
+    let timeout = Duration::from_millis(service.config.timeout_ms);
+    match service.worker().write(subscheme_name, data, timeout) {
+        Some(result) => result,
+        None => {
+            warn!("write operation on {} timed out!", service.config.name);
+            let reason = format!("write to '{}' took longer than {} ms", subscheme_name, service.config.timeout_ms);
+            service.record_event(EventKind::Timeout, &reason);
+            recover(service);
+            Err(Error::new(EBADF))
+        }
+    }
+}
 
-                        Err(_) => {
-                            // must have the same return type, this return value will not be read.
-                            return *thread_buf;
-                        }
-                    }
-                });
-                thread::sleep(Duration::from_millis(50));
-                result = match receiver.try_recv() {
-                    Ok(result) => {
-                        // dropping the reciever here should intterupt the sender's thread, stop trying to read and return
-                        drop(receiver);
-                        read_buf.clone_from_slice(
-                            &read_thread.join().expect("didn't join!?")[0..read_buf.len()],
-                        );
-                        let _close_res = libredox::call::close(read_scheme);
-                        try_again = false;
-                        result
-                    }
-                    Err(_recv_err) => {
-                        drop(receiver);
-                        warn!("read operation on {} timed out!", service.config.name);
-                        // attempt to recover the service, once this returns, if the service is still running then it has ben successfully recovered
-                        if recover(service) {
-                            try_again = true;
-                        }
-                        let _close_res = libredox::call::close(read_scheme);
-                        Err(Error::new(EBADF))
-                    }
-                };
-                // if we made it here we should return an error
-                result
-            }
-            // if we failed to open the base scheme
-            _ => Err(Error::new(EBADF)),
+/// Handles a failed service according to its restart policy.
+///
+/// A service with the `monitor-only` policy is never killed or restarted; it is only marked as failed and keeps
+/// its worker, which [supervise] polls again once the stuck call returns.
+///
+/// The failed process is killed, then the service is either restarted by [schedule_restart] or left stopped.
+/// A service whose process can't be found, such as an unmanaged service that daemonized, can't be killed,
+/// so it is marked unresponsive instead of launching a second copy next to it.
+fn recover(service: &mut ServiceEntry) {
+    if service.config.restart == RestartPolicy::MonitorOnly {
+        if service.state != ServiceState::Failed {
+            error!("'{}' failed and its restart policy is 'monitor-only', it will not be restarted", service.config.name);
+            service.set_state(ServiceState::Failed, EventKind::Recover, "failed, monitor-only services are not restarted");
         }
+        return;
     }
-    result
+    if service.pid == 0 && service.child.is_none() {
+        error!("'{}' failed but its pid is unknown, so it can't be killed and will not be restarted", service.config.name);
+        service.worker = None;
+        service.next_retry_time = 0;
+        service.set_state(ServiceState::Unresponsive, EventKind::Recover, "failed, its pid is unknown so it was not restarted");
+        return;
+    }
+    // a pid of 0 would signal our own process group
+    if service.pid != 0 {
+        let _kill_res = syscall::kill(service.pid, syscall::SIGKILL);
+    }
+    if let Some(mut child) = service.child.take() {
+        let _ = child.wait();
+    }
+    service.worker = None;
+    // the counts last read from the killed process are kept in the totals
+    service.fold_counts();
+    service.set_state(ServiceState::Failed, EventKind::Recover, "killed after failing");
+
+    if service.config.restart == RestartPolicy::Never {
+        warn!("'{}' failed and its restart policy is 'never', leaving it stopped", service.config.name);
+        return;
+    }
+    schedule_restart(service);
 }
 
-/// Function to help write to a service's scheme.
-fn write_helper(service: &mut ServiceEntry, subscheme_name: &str, data: &str) -> Result<usize> {
-    let mut try_again = true;
-    let mut result: Result<usize> = Err(Error::new(EBADF));
-    while try_again {
-        result = match libredox::call::open(service.config.scheme_path.clone(), O_RDWR, 0) {
-            Ok(child_scheme) => {
-                // determine which scheme we are trying to read from
-                let write_scheme = if !subscheme_name.is_empty() {
-                    let data_scheme = libredox::call::dup(child_scheme, subscheme_name.as_bytes())?;
-                    let _close_res = libredox::call::close(child_scheme);
-                    data_scheme
-                } else {
-                    child_scheme
-                };
-                // read from the scheme with a timeout
-                let mut thread_data: [u8; 64] = [0; 64];
-                if data.as_bytes().len() < 64 {
-                    thread_data[0..data.as_bytes().len()]
-                        .clone_from_slice(&data.as_bytes()[0..data.as_bytes().len()]);
-                } else {
-                    thread_data.clone_from_slice(&data.as_bytes()[0..64]);
-                }
-                let (sender, receiver) = mpsc::channel::<Result<usize>>();
-                let write_thread = thread::spawn(move || {
-                    let thread_buf: &mut [u8; 64] = &mut [0; 64];
-                    thread_buf.clone_from_slice(&thread_data[0..thread_data.len()]);
-                    let mut thread_wr = thread_buf.to_vec();
-                    thread_wr.retain(|c| *c != b'\0');
-                    let wr: &[u8] = &thread_wr;
-                    match sender.send(libredox::call::write(write_scheme, wr)) {
-                        Ok(_result) => {
-                            return;
-                        }
-                        Err(_) => {
-                            // must have the same return type, this return value will not be read.
-                            return;
-                        }
-                    }
-                });
-                thread::sleep(Duration::from_millis(50));
-                result = match receiver.try_recv() {
-                    Ok(result) => {
-                        // dropping the reciever here should intterupt the sender's thread, stop trying to read and return
-                        drop(receiver);
-                        write_thread
-                            .join()
-                            .expect("write timeout thread didn't join!");
-                        let _close_res = libredox::call::close(write_scheme);
-                        try_again = false;
-                        result
-                    }
-                    Err(_recv_err) => {
-                        drop(receiver);
-                        warn!("write operation on {} timed out!", service.config.name);
+/// Restarts a service that is no longer running, either right away or after a backoff delay, in which
+/// case [supervise] restarts it later. A service that stops again within `min_uptime_secs` of a restart,
+/// or that is restarted more than `max_restarts` times within the restart window, is marked unresponsive
+/// instead.
+fn schedule_restart(service: &mut ServiceEntry) {
+    let now = Local::now().timestamp_millis();
+    let name = service.config.name.clone();
 
-                        // attempt to recover the service, once this returns, if the service is still running then it has ben successfully recovered
-                        if recover(service) {
-                            try_again = true;
-                        }
-                        let _close_res = libredox::call::close(write_scheme);
-                        Err(Error::new(EBADF))
-                    }
-                };
-                // if we made it here we should return an error
-                result
-            }
-            // if we failed to open the base scheme
-            _ => Err(Error::new(EBADF)),
+    if service.retired {
+        info!("not restarting '{}': it was removed from the registry", name);
+        service.next_retry_time = 0;
+        return;
+    }
+
+    if failed_too_soon(service, now) {
+        error!("'{}' failed again within {} seconds of being restarted, marking it unresponsive", name, service.config.min_uptime_secs);
+        let reason = format!("failed again within {} seconds of being restarted", service.config.min_uptime_secs);
+        service.set_state(ServiceState::Unresponsive, EventKind::Restart, &reason);
+        service.next_retry_time = 0;
+        return;
+    }
+    schedule_retry(service);
+}
+
+/// Returns true if a service stopped within `min_uptime_secs` of its last restart at `now`.
+fn failed_too_soon(service: &ServiceEntry, now: i64) -> bool {
+    service
+        .restart_times
+        .last()
+        .is_some_and(|last_restart| now - last_restart < (service.config.min_uptime_secs * 1000) as i64)
+}
+
+/// Forgets the restarts of a service from before its restart window and returns true if it was still
+/// restarted `max_restarts` times or more within the window at `now`.
+fn restart_limit_reached(service: &mut ServiceEntry, now: i64) -> bool {
+    let window = (service.config.restart_window_secs * 1000) as i64;
+    service.restart_times.retain(|time| now - time < window);
+    service.restart_times.len() >= service.config.max_restarts as usize
+}
+
+/// Restarts a service right away or schedules it to be restarted after a backoff delay, unless it was
+/// restarted more than `max_restarts` times within the restart window, in which case it is marked unresponsive.
+fn schedule_retry(service: &mut ServiceEntry) {
+    let now = Local::now().timestamp_millis();
+    let name = service.config.name.clone();
+    if restart_limit_reached(service, now) {
+        error!(
+            "'{}' was restarted {} times in the last {} seconds, marking it unresponsive",
+            name,
+            service.restart_times.len(),
+            service.config.restart_window_secs
+        );
+        let reason = format!(
+            "restarted {} times in the last {} seconds",
+            service.restart_times.len(),
+            service.config.restart_window_secs
+        );
+        service.set_state(ServiceState::Unresponsive, EventKind::Restart, &reason);
+        service.next_retry_time = 0;
+        return;
+    }
+
+    let delay = backoff_delay(service);
+    if delay > 0 {
+        info!("restarting '{}' in {} ms", name, delay);
+        service.next_retry_time = now + delay as i64;
+        return;
+    }
+    restart(service);
+}
+
+/// Returns how long to wait before restarting a service, based on how many times it was
+/// restarted within the current restart window.
+fn backoff_delay(service: &ServiceEntry) -> u64 {
+    match service.restart_times.len() {
+        0 => 0,
+        restarts => {
+            let exponent = (restarts - 1).min(32) as u32;
+            service
+                .config
+                .backoff_ms
+                .saturating_mul(2u64.saturating_pow(exponent))
+                .min(service.config.max_backoff_ms)
         }
     }
-    result
 }
 
-/// Attempts to restart a service.
-fn recover(service: &mut ServiceEntry) -> bool {
-    let _kill_res = syscall::kill(service.pid, syscall::SIGKILL);
-    service.running = false;
+/// Marks a service whose restart failed as failed and schedules another attempt with [schedule_retry],
+/// so it is retried with backoff until it reaches `max_restarts`.
+fn restart_failed(service: &mut ServiceEntry, reason: &str) {
+    let reason = format!("restart failed: {}", reason);
+    if service.state == ServiceState::Failed {
+        service.record_event(EventKind::Restart, &reason);
+    } else {
+        service.set_state(ServiceState::Failed, EventKind::Restart, &reason);
+    }
+    schedule_retry(service);
+}
+
+/// Launches a failed service again and records the restart. If it cannot be launched, another attempt is scheduled.
+///
+/// This runs inside a supervision tick, so it doesn't wait for the service: it is left starting, and
+/// [poll_restart] finishes the restart on the ticks that follow.
+fn restart(service: &mut ServiceEntry) {
+    service.apply_pending_config();
+    service.next_retry_time = 0;
     service.time_started = Local::now().timestamp_millis(); // where should this go for the start command?
-    let running = match std::process::Command::new(service.config.name.as_str()).spawn() {
-        Ok(mut child) => {
-            let _ = child.wait();
-
-            let child_scheme = libredox::call::open(service.config.scheme_path.clone(), O_RDWR, 1)
-                .expect("couldn't open child scheme");
-            let pid_req = b"pid";
-            let pid_scheme = libredox::call::dup(child_scheme, pid_req).expect("could not get pid");
-
-            let read_buffer: &mut [u8] = &mut [b'0'; 32];
-            libredox::call::read(pid_scheme, read_buffer).expect("could not read pid");
-
-            let _recover_res = match libredox::call::open(service.config.scheme_path.clone(), O_RDWR, 0) {
-                Ok(child_scheme) => {
-                    // determine which scheme we are trying to read from
-                    let pid_scheme = {
-                        let data_scheme = libredox::call::dup(child_scheme, b"pid")
-                            .expect("Failed to dup id scheme during service recovery.");
-                        let _close_res = libredox::call::close(child_scheme);
-                        data_scheme
-                    };
-                    // read from the scheme with a timeout
-                    let (sender, receiver) = mpsc::channel::<Result<usize>>();
-                    let read_thread = thread::spawn(move || {
-                        let thread_buf: &mut [u8; 64] = &mut [0; 64];
-                        match sender.send(libredox::call::read(pid_scheme, thread_buf)) {
-                            Ok(_result) => {
-                                return *thread_buf;
-                            }
+    service.restart_count += 1;
+    service.stats_dirty = true;
+    service.snapshot_dirty = true;
+    service.restart_times.push(service.time_started);
+    reset_for_launch(service);
+    let launched = if service.config.is_managed() {
+        // the launcher exits once the daemon has forked, and the daemon's pid is read once it is ready
+        service
+            .config
+            .command()
+            .and_then(|mut command| command.spawn())
+            .map(|launcher| service.child = Some(launcher))
+            .map_err(|e| format!("failed to launch: {}", e))
+    } else {
+        launch_unmanaged(service)
+    };
+    match launched {
+        Ok(()) => {
+            service.restart_deadline = service.time_started + service.config.start_timeout_ms as i64;
+            service.set_state(ServiceState::Starting, EventKind::Restart, "restarting after failing");
+        }
+        Err(reason) => {
+            warn!("could not restart {}: {}", service.config.name, reason);
+            restart_failed(service, &reason);
+        }
+    }
+}
 
-                            Err(_) => {
-                                // must have the same return type, this return value will not be read.
-                                return *thread_buf;
-                            }
-                        }
-                    });
-                    thread::sleep(Duration::from_millis(50));
-                    let final_res = match receiver.try_recv() {
-                        Ok(result) => {
-                            // dropping the reciever here should intterupt the sender's thread, stop trying to read and return
-                            drop(receiver);
-                            info!("recovered {} from timeout", service.config.name);
-                            read_buffer.clone_from_slice(
-                                &read_thread.join().expect("didn't join!?")[0..read_buffer.len()],
-                            );
-                            result
-                        }
-                        Err(_recv_err) => {
-                            drop(receiver);
-                            // for now just kill the service that timed out and return an error
-                            let _ = syscall::call::kill(service.pid, syscall::SIGKILL);
-                            service.running = false;
-                            Err(Error::new(EBADF))
-                        }
-                    };
-                    let _close_res = libredox::call::close(pid_scheme);
-                    final_res
+/// Moves a restart launched by [restart] along by one step. A managed service's launcher has to finish the
+/// daemon handshake first, and then the service's `ready_check` is run once. A service that isn't ready by
+/// its `start_timeout_ms` is killed, and another restart is scheduled.
+fn poll_restart(service: &mut ServiceEntry, now: i64) {
+    let timed_out = now >= service.restart_deadline;
+    if service.config.is_managed() {
+        if let Some(launcher) = service.child.as_mut() {
+            match launcher.try_wait() {
+                Ok(None) if !timed_out => return,
+                Ok(None) => {
+                    let reason = format!("Launcher did not finish within {} ms", service.config.start_timeout_ms);
+                    service.record_event(EventKind::Timeout, &reason);
+                    return fail_restart(service, &reason);
                 }
-                // if we failed to open the base scheme
-                _ => Err(Error::new(EBADF)),
-            };
-            // process the buffer based on the request
-            let mut pid_bytes: [u8; 8] = [0; 8];
-            pid_bytes.clone_from_slice(&read_buffer[0..8]);
-            let pid = usize::from_ne_bytes(pid_bytes);
+                Ok(Some(_status)) => service.child = None,
+                Err(e) => return fail_restart(service, &format!("Failed to wait for the launcher: {}", e)),
+            }
+        }
+    }
+    let ready = match check_ready(service) {
+        Ok(true) if service.config.is_managed() => read_pid(service).map(|pid| {
             service.pid = pid;
-            info!("child started with pid: {:#?}", service.pid);
-            service.running = true;
-            service.running
+            true
+        }),
+        other => other,
+    };
+    match ready {
+        Ok(true) => {
+            service.restart_deadline = 0;
+            service.time_ready = Local::now().timestamp_millis();
+            info!("'{}' restarted with pid {}", service.config.name, service.pid);
+            service.set_state(ServiceState::Restarted, EventKind::Restart, &format!("restarted with pid {}", service.pid));
         }
+        _ if !timed_out => {}
+        Ok(false) => fail_restart(service, &format!("Not ready within {} ms: the service has not reported that it is ready", service.config.start_timeout_ms)),
+        Err(reason) => fail_restart(service, &format!("Not ready within {} ms: {}", service.config.start_timeout_ms, reason)),
+    }
+}
 
-        Err(_e) => {
-            warn!("start failed: could not restart {}", service.config.name);
-            service.running
+/// Kills what a restart that didn't become ready launched, then schedules another attempt.
+fn fail_restart(service: &mut ServiceEntry, reason: &str) {
+    warn!("restarted {} but it did not become ready: {}", service.config.name, reason);
+    kill_launched(service);
+    restart_failed(service, reason);
+}
+
+/// Kills the processes of a restart that is still starting, without waiting for a graceful shutdown.
+fn kill_launched(service: &mut ServiceEntry) {
+    service.restart_deadline = 0;
+    // the launcher of a managed service, or the process of an unmanaged one
+    if let Some(mut child) = service.child.take() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    if service.config.is_managed() {
+        if let Ok(pid) = read_pid(service) {
+            service.pid = pid;
         }
-    };
-    running
-}
\ No newline at end of file
+        // a pid of 0 would signal our own process group
+        if service.pid != 0 {
+            let _kill_res = syscall::kill(service.pid, syscall::SIGKILL);
+        } else if !request_stop(service) {
+            warn!("'{}' failed to become ready and may still be running, its pid is unknown", service.config.name);
+        }
+    }
+    service.worker = None;
+}
+
+/// Reads the pid of a managed service that was just launched, using a new worker since any previous one
+/// belonged to the service's old process.
+fn read_pid(service: &mut ServiceEntry) -> std::result::Result<usize, String> {
+    service.worker = None;
+    let read_buffer: &mut [u8; 32] = &mut [0; 32];
+    let timeout = Duration::from_millis(service.config.timeout_ms);
+    match service.worker().read("pid", read_buffer, timeout) {
+        Some(Ok(_size)) => {
+            let mut pid_bytes: [u8; 8] = [0; 8];
+            pid_bytes.clone_from_slice(&read_buffer[0..8]);
+            Ok(usize::from_ne_bytes(pid_bytes))
+        }
+        Some(Err(e)) => Err(format!("Failed to read pid from '{}': {}", service.config.scheme_path, e)),
+        None => {
+            service.worker = None;
+            Err(format!("Timed out after {} ms reading pid from '{}'", service.config.timeout_ms, service.config.scheme_path))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use registry::Service;
+
+    fn service(backoff_ms: u64, max_backoff_ms: u64) -> ServiceEntry {
+        ServiceEntry::new(Service {
+            name: "a".to_string(),
+            backoff_ms,
+            max_backoff_ms,
+            max_restarts: 3,
+            restart_window_secs: 60,
+            min_uptime_secs: 5,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn first_restart_has_no_backoff() {
+        assert_eq!(backoff_delay(&service(100, 1000)), 0);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let mut service = service(100, 1000);
+        let delays: Vec<u64> = (0..6)
+            .map(|restart| {
+                service.restart_times.push(restart);
+                backoff_delay(&service)
+            })
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+    }
+
+    #[test]
+    fn backoff_does_not_overflow() {
+        let mut service = service(u64::MAX / 2, u64::MAX);
+        service.restart_times = vec![0; 100];
+        assert_eq!(backoff_delay(&service), u64::MAX);
+    }
+
+    #[test]
+    fn restarts_outside_the_window_are_forgotten() {
+        let mut service = service(0, 0);
+        service.restart_times = vec![0, 1_000, 50_000];
+        assert!(restart_limit_reached(&mut service, 55_000));
+        assert!(!restart_limit_reached(&mut service, 61_000));
+        assert_eq!(service.restart_times, vec![50_000]);
+    }
+
+    #[test]
+    fn restart_limit_is_reached_at_max_restarts() {
+        let mut service = service(0, 0);
+        service.restart_times = vec![1_000, 2_000];
+        assert!(!restart_limit_reached(&mut service, 3_000));
+        service.restart_times.push(3_000);
+        assert!(restart_limit_reached(&mut service, 3_000));
+    }
+
+    #[test]
+    fn failing_within_min_uptime_is_too_soon() {
+        let mut service = service(0, 0);
+        assert!(!failed_too_soon(&service, 1_000));
+        service.restart_times.push(10_000);
+        assert!(failed_too_soon(&service, 14_999));
+        assert!(!failed_too_soon(&service, 15_000));
+    }
+}
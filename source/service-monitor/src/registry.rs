@@ -1,24 +1,492 @@
 use hashbrown::HashMap;
-use log::warn;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
-use shared::{TOMLMessage};
-use std::{fs::File, io::Read, io::Write, path::Path};
+use shared::{EventKind, HealthStatus, ServiceEvent, ServiceState, TOMLMessage};
+use toml_edit::{value, DocumentMut, Table};
+use crate::worker::ServiceWorker;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::Read,
+    io::Write,
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread::{self, JoinHandle},
+};
 
 /// Struct defining a service's configuration within the registry.
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Service {
     /// Name of the service.
     pub name: String,
     /// The type of service. Current valid options are "unmanaged" and "daemon"; All other values are treated as "daemon".
     pub r#type: String,
+    /// The path to the executable that starts the service. If empty, the service's name is used instead.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub exec: String,
     /// The command-line args used to start the service with.
     pub args: Vec<String>,
-    /// Informs the service monitor to not "correct" this service's configuration. Currently has no effect.
+    /// Environment variables set for the service, on top of those inherited from the service monitor.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// The working directory the service is started in. If empty, the service monitor's working directory is used.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cwd: String,
+    /// Where the service's stdin is read from.
+    #[serde(default)]
+    pub stdin: StdioConfig,
+    /// Where the service's stdout is written to.
+    #[serde(default)]
+    pub stdout: StdioConfig,
+    /// Where the service's stderr is written to.
+    #[serde(default)]
+    pub stderr: StdioConfig,
+    /// Keeps the service monitor from writing its corrections to this service's entry back to the registry.
+    /// The corrections are still used while the service monitor runs. See [Service::correct].
     pub manual_override: bool,
     /// A list of the names of services this service depends on.
     pub depends: Vec<String>,
     /// The path to the scheme of the service.
     pub scheme_path: String,
+    /// If false, the service is only started when requested, and not at boot or when it is added by a reload.
+    #[serde(default = "default_autostart", skip_serializing_if = "is_autostart")]
+    pub autostart: bool,
+    /// How often, in milliseconds, the service monitor checks on this service. Overrides [MonitorConfig::health_interval_ms] if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_interval_ms: Option<u64>,
+    /// When the service monitor restarts this service after it fails.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    /// The most times this service is restarted within `restart_window_secs` before the service monitor gives up on it.
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+    /// The length, in seconds, of the window that `max_restarts` applies to.
+    #[serde(default = "default_restart_window")]
+    pub restart_window_secs: u64,
+    /// The delay, in milliseconds, before restarting this service after its second failure within the restart window.
+    /// The delay doubles with each further failure, up to `max_backoff_ms`. The first failure is always restarted immediately.
+    #[serde(default = "default_backoff")]
+    pub backoff_ms: u64,
+    /// The longest delay, in milliseconds, between restarts.
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff_ms: u64,
+    /// If this service fails again within this many seconds of being restarted, it is marked unresponsive and left stopped.
+    #[serde(default = "default_min_uptime")]
+    pub min_uptime_secs: u64,
+    /// How long, in milliseconds, the service monitor waits for this service to exit after asking it to stop
+    /// through its control subscheme, before sending it SIGTERM.
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout_ms: u64,
+    /// How long, in milliseconds, the service monitor waits for this service to exit after sending it SIGTERM,
+    /// before sending it SIGKILL.
+    #[serde(default = "default_term_timeout")]
+    pub term_timeout_ms: u64,
+    /// How long, in milliseconds, a read or write on this service's scheme may take before the
+    /// service is considered hung and is recovered.
+    #[serde(default = "default_timeout")]
+    pub timeout_ms: u64,
+    /// How the service monitor decides this service is ready once it has been launched.
+    #[serde(default)]
+    pub ready_check: ReadyCheck,
+    /// How long, in milliseconds, this service has to become ready after it is launched before it is
+    /// stopped and marked as failed. An unmanaged service's process that exits successfully within this
+    /// time is assumed to have daemonized if its scheme can be opened.
+    #[serde(default = "default_start_timeout")]
+    pub start_timeout_ms: u64,
+    /// Checks run against this service's scheme to tell whether it is healthy, written as `[[service.probe]]` tables.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub probe: Vec<Probe>,
+    /// How often, in milliseconds, this service's probes are run. A value of 0 disables them.
+    #[serde(default = "default_probe_interval")]
+    pub probe_interval_ms: u64,
+    /// How many times in a row the probes have to fail before this service is recovered according to its restart policy.
+    #[serde(default = "default_probe_failure_threshold")]
+    pub probe_failure_threshold: u32,
+    /// A command run periodically to judge how healthy this service is, written as a `[service.health_check]` table.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+}
+
+impl Default for Service {
+    fn default() -> Self {
+        Service {
+            name: String::new(),
+            r#type: String::new(),
+            exec: String::new(),
+            args: Vec::new(),
+            env: BTreeMap::new(),
+            cwd: String::new(),
+            stdin: StdioConfig::default(),
+            stdout: StdioConfig::default(),
+            stderr: StdioConfig::default(),
+            manual_override: false,
+            depends: Vec::new(),
+            scheme_path: String::new(),
+            autostart: default_autostart(),
+            health_interval_ms: None,
+            restart: RestartPolicy::default(),
+            max_restarts: default_max_restarts(),
+            restart_window_secs: default_restart_window(),
+            backoff_ms: default_backoff(),
+            max_backoff_ms: default_max_backoff(),
+            min_uptime_secs: default_min_uptime(),
+            stop_timeout_ms: default_stop_timeout(),
+            term_timeout_ms: default_term_timeout(),
+            timeout_ms: default_timeout(),
+            ready_check: ReadyCheck::default(),
+            start_timeout_ms: default_start_timeout(),
+            probe: Vec::new(),
+            probe_interval_ms: default_probe_interval(),
+            probe_failure_threshold: default_probe_failure_threshold(),
+            health_check: None,
+        }
+    }
+}
+
+fn default_autostart() -> bool {
+    true
+}
+
+fn is_autostart(autostart: &bool) -> bool {
+    *autostart
+}
+
+fn default_max_restarts() -> u32 {
+    5
+}
+
+fn default_restart_window() -> u64 {
+    60
+}
+
+fn default_backoff() -> u64 {
+    500
+}
+
+fn default_max_backoff() -> u64 {
+    30000
+}
+
+fn default_min_uptime() -> u64 {
+    5
+}
+
+fn default_stop_timeout() -> u64 {
+    2000
+}
+
+fn default_term_timeout() -> u64 {
+    1000
+}
+
+fn default_probe_interval() -> u64 {
+    10000
+}
+
+fn default_probe_failure_threshold() -> u32 {
+    3
+}
+
+fn default_start_timeout() -> u64 {
+    5000
+}
+
+fn default_timeout() -> u64 {
+    500
+}
+
+/// Describes when a failed service is restarted.
+///
+/// In the registry this is written as `"never"`, `"on-failure"`, `"always"`, or `"monitor-only"`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// The service is left stopped when it fails.
+    Never,
+    /// The service is restarted when it fails or stops responding.
+    #[default]
+    OnFailure,
+    /// The service is restarted whenever it stops, even if it exited cleanly.
+    Always,
+    /// The service is never killed or restarted. When it fails it is only marked as failed, and the service
+    /// monitor keeps polling it through its one worker, marking it running again once it responds. Meant for
+    /// services that can only safely run once, such as ACPI-AML.
+    MonitorOnly,
+}
+
+impl RestartPolicy {
+    /// Returns true if a service whose process exited on its own is restarted under this policy.
+    /// `failed` is whether the exit was a failure rather than a clean stop.
+    pub fn restarts_after_exit(&self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Never | RestartPolicy::MonitorOnly => false,
+        }
+    }
+}
+
+/// How the service monitor decides that a service it launched is ready, so that its dependents can be started.
+///
+/// In the registry this is written as `"pid"`, `"scheme"`, or `"ready"`.
+/// Unmanaged services have no management subschemes, so only `"scheme"` is checked for them.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReadyCheck {
+    /// The service's "pid" subscheme can be read.
+    #[default]
+    Pid,
+    /// The service's scheme can be opened.
+    Scheme,
+    /// The service has set the ready bit in its "ready" subscheme, with `BaseScheme::set_ready()`.
+    Ready,
+}
+
+/// A check run against a service's main scheme. It passes if every step succeeds within its timeout.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Probe {
+    /// What the probe does once it has opened `path`.
+    pub kind: ProbeKind,
+    /// The path opened within the service's scheme, e.g. "seed" opens `<scheme_path>/seed`. If empty, the scheme itself is opened.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
+    /// The number of bytes a "read" probe expects to read.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub len: usize,
+    /// The bytes a "write" probe writes.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub payload: String,
+    /// How long, in milliseconds, the probe may take. Defaults to the service's `timeout_ms`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+fn is_zero(len: &usize) -> bool {
+    *len == 0
+}
+
+impl std::fmt::Display for Probe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = if self.path.is_empty() { String::from("the scheme") } else { format!("'{}'", self.path) };
+        match self.kind {
+            ProbeKind::Read => write!(f, "read of {} bytes from {}", self.len, path),
+            _ => write!(f, "{} of {}", self.kind, path),
+        }
+    }
+}
+
+/// A command the service monitor runs against a running service to judge its health.
+///
+/// The command's exit code is interpreted with [HealthStatus::from_exit_code]: 0 is healthy, 1 is degraded,
+/// and anything else is unhealthy.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct HealthCheck {
+    /// The path to the executable that is run.
+    pub exec: String,
+    /// The arguments passed to `exec`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// How long, in milliseconds, the command may run before it is killed and the service is considered unhealthy.
+    #[serde(default = "default_health_check_timeout")]
+    pub timeout_ms: u64,
+    /// How often, in milliseconds, the command is run. A value of 0 disables it.
+    #[serde(default = "default_health_check_interval")]
+    pub interval_ms: u64,
+}
+
+fn default_health_check_timeout() -> u64 {
+    2000
+}
+
+fn default_health_check_interval() -> u64 {
+    30000
+}
+
+impl HealthCheck {
+    /// Builds the [Command] that runs this health check, with its output captured.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.exec);
+        command
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        command
+    }
+
+    /// Starts a run of this health check. Its output is read as it is printed, so a check that prints more
+    /// than a pipe holds doesn't block before it exits.
+    pub fn spawn(&self) -> std::io::Result<HealthCheckRun> {
+        let mut child = self.command().spawn()?;
+        let stdout = child.stdout.take().map(drain);
+        let stderr = child.stderr.take().map(drain);
+        Ok(HealthCheckRun { child, stdout, stderr })
+    }
+}
+
+/// The most bytes of each of a health check command's output streams that are kept.
+const HEALTH_OUTPUT_LIMIT: u64 = 256;
+
+/// Reads `pipe` on its own thread until it is closed, keeping only the first [HEALTH_OUTPUT_LIMIT] bytes.
+fn drain(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output: Vec<u8> = Vec::new();
+        let _ = pipe.by_ref().take(HEALTH_OUTPUT_LIMIT).read_to_end(&mut output);
+        let _ = std::io::copy(&mut pipe, &mut std::io::sink());
+        output
+    })
+}
+
+/// A run of a service's `health_check` command that the service monitor is waiting on.
+pub struct HealthCheckRun {
+    pub child: Child,
+    stdout: Option<JoinHandle<Vec<u8>>>,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+}
+
+impl HealthCheckRun {
+    /// Returns true once everything the command printed has been read, which can be a little after it exits.
+    pub fn output_read(&self) -> bool {
+        [&self.stdout, &self.stderr].iter().all(|reader| reader.as_ref().is_none_or(|reader| reader.is_finished()))
+    }
+
+    /// Returns the start of what the command printed to stdout, or to stderr if stdout was empty.
+    /// Output that is still being read is left out, since the command may have left a process holding its pipe.
+    pub fn output(&mut self) -> String {
+        let take = |reader: &mut Option<JoinHandle<Vec<u8>>>| match reader.take() {
+            Some(reader) if reader.is_finished() => reader.join().unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        let mut output = take(&mut self.stdout);
+        if output.iter().all(|byte| byte.is_ascii_whitespace()) {
+            output = take(&mut self.stderr);
+        }
+        String::from_utf8_lossy(&output).trim().to_string()
+    }
+}
+
+/// What a [Probe] does. In the registry this is written as `"open"`, `"read"`, `"write"`, or `"fstat"`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProbeKind {
+    /// Only opens the path.
+    #[default]
+    Open,
+    /// Reads `len` bytes, failing if fewer are read.
+    Read,
+    /// Writes `payload`.
+    Write,
+    /// Calls fstat on the opened path.
+    Fstat,
+}
+
+impl std::fmt::Display for ProbeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeKind::Open => write!(f, "open"),
+            ProbeKind::Read => write!(f, "read"),
+            ProbeKind::Write => write!(f, "write"),
+            ProbeKind::Fstat => write!(f, "fstat"),
+        }
+    }
+}
+
+impl Service {
+    /// Returns false if this is an old-style daemon that does not implement the management subschemes.
+    pub fn is_managed(&self) -> bool {
+        self.r#type != "unmanaged"
+    }
+
+    /// Corrects the fields of this entry that the service monitor can verify, and returns a description of each correction:
+    /// - a `type` other than "daemon" or "unmanaged" is normalized, and anything but "unmanaged" becomes "daemon"
+    /// - a managed service without a `scheme_path` gets the default of `/scheme/<name>`
+    /// - an executable that isn't a path is resolved from `PATH`
+    pub fn correct(&mut self) -> Vec<String> {
+        let mut corrections = Vec::new();
+
+        let r#type = if self.r#type.trim().eq_ignore_ascii_case("unmanaged") { "unmanaged" } else { "daemon" };
+        if self.r#type != r#type {
+            corrections.push(format!("type corrected from '{}' to '{}'", self.r#type, r#type));
+            self.r#type = r#type.to_string();
+        }
+
+        if self.is_managed() && self.scheme_path.is_empty() {
+            self.scheme_path = format!("/scheme/{}", self.name);
+            corrections.push(format!("scheme_path corrected from '' to '{}'", self.scheme_path));
+        }
+
+        if !self.executable().contains('/') {
+            match resolve_executable(self.executable()) {
+                Some(path) => {
+                    corrections.push(format!("exec corrected from '{}' to '{}'", self.exec, path));
+                    self.exec = path;
+                }
+                None => warn!("'{}': executable '{}' was not found in PATH", self.name, self.executable()),
+            }
+        }
+        corrections
+    }
+
+    /// Returns the executable used to start this service.
+    pub fn executable(&self) -> &str {
+        if self.exec.is_empty() {
+            &self.name
+        } else {
+            &self.exec
+        }
+    }
+
+    /// Builds the [Command] that launches this service with its configured args, environment,
+    /// working directory, and stdio.
+    ///
+    /// Both the initial start and recovery use this so a restarted service comes back with the same configuration.
+    pub fn command(&self) -> std::io::Result<Command> {
+        let mut command = Command::new(self.executable());
+        command
+            .args(&self.args)
+            .envs(&self.env)
+            .stdin(self.stdin.to_stdio(false)?)
+            .stdout(self.stdout.to_stdio(true)?)
+            .stderr(self.stderr.to_stdio(true)?);
+        if !self.cwd.is_empty() {
+            command.current_dir(&self.cwd);
+        }
+        Ok(command)
+    }
+}
+
+/// Describes how one of a service's standard streams is connected when it is started.
+///
+/// In the registry this is written as `"inherit"`, `"null"`, or `{ file = "/path/to/file" }`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StdioConfig {
+    /// Share the service monitor's stream.
+    #[default]
+    Inherit,
+    /// Discard output, or provide no input.
+    Null,
+    /// Read input from, or append output to, the file at the given path.
+    File(String),
+}
+
+impl StdioConfig {
+    /// Converts this configuration into a [Stdio]. If `output` is true, files are opened
+    /// for appending (and created if missing), otherwise they are opened for reading.
+    fn to_stdio(&self, output: bool) -> std::io::Result<Stdio> {
+        match self {
+            StdioConfig::Inherit => Ok(Stdio::inherit()),
+            StdioConfig::Null => Ok(Stdio::null()),
+            StdioConfig::File(path) => {
+                let file = if output {
+                    OpenOptions::new().create(true).append(true).open(path)?
+                } else {
+                    File::open(path)?
+                };
+                Ok(Stdio::from(file))
+            }
+        }
+    }
 }
 
 /// Struct defining a service's registry configuration and its runtime statistics.
@@ -27,14 +495,28 @@ pub struct Service {
 pub struct ServiceEntry {
     /// A copy of the service's registry configuration.
     pub config: Service,
-    /// Whether this service is currently alive.
-    pub running: bool,
-    /// The service's process ID.
+    /// The service's lifecycle state. Use [ServiceEntry::set_state] to change it.
+    pub state: ServiceState,
+    /// The service's process ID, or 0 if it is not known.
     pub pid: usize,
+    /// The process of an unmanaged service that runs in the foreground, kept so it can be waited on.
+    pub child: Option<Child>,
+    /// The worker doing I/O on this service's scheme. It is started on first use and dropped whenever
+    /// the service's process changes or the worker gets stuck.
+    pub worker: Option<ServiceWorker>,
     /// The timestamp, in milliseconds from the Unix epoch, that this service was last started.
     pub time_started: i64,
     /// The timestamp, in milliseconds from the Unix epoch, that this service was initialized.
     pub time_init: i64,
+    /// The timestamp, in milliseconds from the Unix epoch, that this service passed its `ready_check` after it was
+    /// last started, or 0 if it has not.
+    pub time_ready: i64,
+    /// The timestamp, in milliseconds from the Unix epoch, until which a clean exit of an unmanaged service's
+    /// process means it may have daemonized, or 0 once it is known whether the process runs in the foreground.
+    pub daemonize_deadline: i64,
+    /// The timestamp, in milliseconds from the Unix epoch, by which a restart started by supervision has to
+    /// pass its `ready_check`, or 0 if no restart is in progress.
+    pub restart_deadline: i64,
     pub read_count: u64,
     pub write_count: u64,
     pub open_count: u64,
@@ -47,13 +529,270 @@ pub struct ServiceEntry {
     pub total_closes: u64,
     pub total_dups: u64,
     pub total_errors: u64,
-    /// The timestamp, in milliseconds from the Unix epoch, that this service last updated its message.
+    /// The timestamp, in milliseconds from the Unix epoch, that this service last responded to the service monitor.
     pub last_response_time: i64,
+    /// The timestamp, in milliseconds from the Unix epoch, of the last periodic health check on this service.
+    pub last_health_check: i64,
+    /// The timestamp, in milliseconds from the Unix epoch, that this service's probes were last run.
+    pub last_probe: i64,
+    /// How many times in a row this service's probes have failed.
+    pub probe_failures: u32,
+    /// The run of this service's `health_check` command that has not finished yet.
+    pub health_check_run: Option<HealthCheckRun>,
+    /// The timestamp, in milliseconds from the Unix epoch, that the `health_check` command was last run.
+    pub health_check_started: i64,
+    /// The result of the `health_check` command the last time it finished since this service was started.
+    pub health: HealthStatus,
+    /// The start of what the `health_check` command printed the last time it finished.
+    pub health_output: String,
+    /// The timestamp, in milliseconds from the Unix epoch, that the `health_check` command last finished.
+    pub health_time: i64,
+    /// The number of times the service monitor has restarted this service after a failure.
+    pub restart_count: u32,
+    /// The timestamps, in milliseconds from the Unix epoch, of the restarts within the current restart window.
+    pub restart_times: Vec<i64>,
+    /// The timestamp, in milliseconds from the Unix epoch, that a pending restart is scheduled for, or 0 if none is.
+    pub next_retry_time: i64,
+    /// How the service's process last exited, if the service monitor has seen it exit.
+    pub last_exit: Option<ExitInfo>,
+    /// True if the lifetime totals, restart count, or last exit changed since the stats file was last written.
+    pub stats_dirty: bool,
+    /// True if the pid, state, or start time changed since the runtime snapshot was last written.
+    pub snapshot_dirty: bool,
+    /// Events that happened to this service and have not been taken into the event journal yet.
+    pub events: Vec<ServiceEvent>,
+    /// A configuration reloaded from the registry while this service was running, which it switches to
+    /// the next time it is started.
+    pub pending_config: Option<Service>,
+    /// True if this service was removed from the registry while it was running. It is dropped from the
+    /// service monitor once it stops, and is not restarted.
+    pub retired: bool,
     /// A human-readable message reported by the service.
     pub message: String,
     pub message_time: i64,
 }
 
+/// Describes how a service's process exited.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ExitInfo {
+    /// The timestamp, in milliseconds from the Unix epoch, that the exit was detected.
+    pub time: i64,
+    /// The process's exit code, if it exited normally and the service monitor could reap it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<i32>,
+    /// The signal that terminated the process, if it was killed and the service monitor could reap it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+}
+
+impl ExitInfo {
+    /// Returns true if the process exited with a nonzero code or was killed by a signal. An exit whose status
+    /// is unknown, because the process was not the service monitor's child, is not counted as a failure.
+    pub fn failed(&self) -> bool {
+        self.code.is_some_and(|code| code != 0) || self.signal.is_some()
+    }
+}
+
+impl std::fmt::Display for ExitInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exited with code {}", code),
+            (None, Some(signal)) => write!(f, "killed by signal {}", signal),
+            (None, None) => write!(f, "exited with unknown status"),
+        }
+    }
+}
+
+impl ServiceEntry {
+    /// Creates a [ServiceEntry] for a service that has not been started yet.
+    pub fn new(config: Service) -> ServiceEntry {
+        ServiceEntry {
+            config,
+            state: ServiceState::Stopped,
+            pid: 0,
+            child: None,
+            worker: None,
+            time_started: 0,
+            time_init: 0,
+            time_ready: 0,
+            daemonize_deadline: 0,
+            restart_deadline: 0,
+            read_count: 0,
+            write_count: 0,
+            open_count: 0,
+            close_count: 0,
+            dup_count: 0,
+            error_count: 0,
+            total_reads: 0,
+            total_writes: 0,
+            total_opens: 0,
+            total_closes: 0,
+            total_dups: 0,
+            total_errors: 0,
+            last_response_time: 0,
+            last_health_check: 0,
+            last_probe: 0,
+            probe_failures: 0,
+            health_check_run: None,
+            health_check_started: 0,
+            health: HealthStatus::Unknown,
+            health_output: String::new(),
+            health_time: 0,
+            restart_count: 0,
+            restart_times: Vec::new(),
+            next_retry_time: 0,
+            last_exit: None,
+            stats_dirty: false,
+            snapshot_dirty: false,
+            events: Vec::new(),
+            pending_config: None,
+            retired: false,
+            message: String::new(),
+            message_time: 0,
+        }
+    }
+
+    /// Adds the short-term request and message counts to the lifetime totals and resets them. This is done
+    /// whenever the counts are about to be lost, when the service is cleared or its process is replaced.
+    pub fn fold_counts(&mut self) {
+        self.total_reads += self.read_count;
+        self.total_writes += self.write_count;
+        self.total_opens += self.open_count;
+        self.total_closes += self.close_count;
+        self.total_dups += self.dup_count;
+        self.total_errors += self.error_count;
+        self.read_count = 0;
+        self.write_count = 0;
+        self.open_count = 0;
+        self.close_count = 0;
+        self.dup_count = 0;
+        self.error_count = 0;
+        self.stats_dirty = true;
+    }
+
+    /// Returns true if this service is fully up and serving.
+    pub fn is_running(&self) -> bool {
+        self.state.is_running()
+    }
+
+    /// Returns the worker doing I/O on this service's scheme, starting it if needed.
+    pub fn worker(&mut self) -> &ServiceWorker {
+        let config = &self.config;
+        self.worker.get_or_insert_with(|| ServiceWorker::spawn(&config.name, &config.scheme_path))
+    }
+
+    /// Returns true if this service has a process the service monitor is still tracking. Unlike [ServiceEntry::is_running],
+    /// this includes a failed service with the `monitor-only` restart policy, which is never killed, and a service
+    /// that is being restarted.
+    pub fn has_process(&self) -> bool {
+        self.is_running()
+            || self.restart_deadline != 0
+            || (self.state == ServiceState::Failed && self.config.restart == RestartPolicy::MonitorOnly && !self.has_exited())
+    }
+
+    /// Returns true if this service's process was seen exiting since it was last started.
+    pub fn has_exited(&self) -> bool {
+        self.last_exit.as_ref().is_some_and(|exit| exit.time >= self.time_started)
+    }
+
+    /// Switches to the configuration that was reloaded while this service was running, if there is one.
+    /// Should only be called while the service is not running.
+    pub fn apply_pending_config(&mut self) {
+        if let Some(config) = self.pending_config.take() {
+            info!("'{}' is switching to its reloaded configuration", self.config.name);
+            self.config = config;
+            // the worker may be on the old scheme path
+            self.worker = None;
+        }
+    }
+
+    /// Moves this service to `state`, logging the transition and recording it as a `kind` event.
+    pub fn set_state(&mut self, state: ServiceState, kind: EventKind, reason: &str) {
+        if self.state != state {
+            info!("'{}': {} -> {}", self.config.name, self.state, state);
+            self.push_event(kind, state, reason);
+            self.state = state;
+            self.snapshot_dirty = true;
+        }
+    }
+
+    /// Records a `kind` event that does not change this service's state.
+    pub fn record_event(&mut self, kind: EventKind, reason: &str) {
+        self.push_event(kind, self.state, reason);
+    }
+
+    fn push_event(&mut self, kind: EventKind, new_state: ServiceState, reason: &str) {
+        self.events.push(ServiceEvent {
+            time: chrono::Local::now().timestamp_millis(),
+            service: self.config.name.clone(),
+            kind,
+            old_state: self.state,
+            new_state,
+            reason: reason.to_string(),
+        });
+    }
+}
+
+/// Global settings for the service monitor, stored in the `[monitor]` table of the registry.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MonitorConfig {
+    /// How often, in milliseconds, the service monitor checks on each running service.
+    /// A value of 0 disables periodic checks.
+    #[serde(default = "default_health_interval")]
+    pub health_interval_ms: u64,
+    /// How many events the event journal keeps before dropping the oldest.
+    #[serde(default = "default_event_capacity")]
+    pub event_capacity: usize,
+    /// If true, the event journal is saved to disk and reloaded when the service monitor starts.
+    #[serde(default)]
+    pub persist_events: bool,
+    /// The most services that are started at once at boot or by `services start @target`.
+    /// Each service still waits until all of its dependencies are ready.
+    #[serde(default = "default_max_parallel_starts")]
+    pub max_parallel_starts: usize,
+    /// How often, in milliseconds, the service monitor runs a supervision tick when no requests arrive.
+    /// Exits, probes, health checks, and restarts are all driven from these ticks.
+    #[serde(default = "default_supervise_interval")]
+    pub supervise_interval_ms: u64,
+}
+
+fn default_supervise_interval() -> u64 {
+    100
+}
+
+fn default_max_parallel_starts() -> usize {
+    4
+}
+
+fn default_event_capacity() -> usize {
+    256
+}
+
+fn default_health_interval() -> u64 {
+    5000
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        MonitorConfig {
+            health_interval_ms: default_health_interval(),
+            event_capacity: default_event_capacity(),
+            persist_events: false,
+            max_parallel_starts: default_max_parallel_starts(),
+            supervise_interval_ms: default_supervise_interval(),
+        }
+    }
+}
+
+/// A named set of services that are started and stopped together, stored in a `[[target]]` table of the registry.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Target {
+    pub name: String,
+    /// The services in this target. Another target is included by giving its name prefixed with `@`.
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
 /// A helper-struct used by the TOML parser to read/write to and from the registry on disk.
 /// 
 /// Using this directly is not very useful. To interact with the registry on disk, use:
@@ -65,7 +804,11 @@ pub struct ServiceEntry {
 /// - [rm_entry]
 #[derive(Debug, Deserialize, Serialize)]
 struct Registry {
+    #[serde(default)]
+    monitor: MonitorConfig,
     service: Vec<Service>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    target: Vec<Target>,
 }
 
 /// Constructs a [HashMap] of service name [String]s mapped to [ServiceEntry] objects
@@ -74,17 +817,191 @@ struct Registry {
 /// # Panics
 /// This function will panic if `registry.toml` is unable to be opened and read from as a UTF-8 TOML string.
 pub fn read_registry() -> HashMap<String, ServiceEntry> {
-    // TODO: determine filepath (where will registry.toml be located?)
-    // ! This filepath is just a temporary solution
-    let path: &Path = Path::new("/usr/share/smregistry.toml");
+    // Sets up the services map for main.
+    let mut services: HashMap<String, ServiceEntry> = HashMap::new();
+    for s in read_registry_file().service {
+        let new_entry = ServiceEntry::new(s);
+        services.insert(new_entry.config.name.clone(), new_entry);
+    }
+    return services;
+}
+
+/// Reads the service monitor's global settings from the `[monitor]` table of the registry.
+/// 
+/// # Panics
+/// This function will panic if `registry.toml` is unable to be opened and read from as a UTF-8 TOML string.
+pub fn read_config() -> MonitorConfig {
+    read_registry_file().monitor
+}
+
+/// Reads the targets in the registry, keyed by target name.
+pub fn read_targets() -> Result<HashMap<String, Target>, String> {
+    let registry = try_read_registry_file()?;
+    Ok(registry
+        .target
+        .into_iter()
+        .map(|target| (target.name.clone(), target))
+        .collect())
+}
+
+/// Finds the executable `name` in the directories listed in `PATH`.
+fn resolve_executable(name: &str) -> Option<String> {
+    let paths = std::env::var("PATH").ok()?;
+    paths
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+/// Corrects the registry entries in `configs` with [Service::correct], logging every correction.
+///
+/// The corrections are written back to the on-disk registry, except for entries with `manual_override` set
+/// and for executables resolved from `PATH`.
+pub fn correct_entries<'a>(configs: impl Iterator<Item = &'a mut Service>) {
+    let mut corrected: Vec<String> = Vec::new();
+    for config in configs {
+        let corrections = config.correct();
+        for correction in &corrections {
+            if config.manual_override {
+                info!("'{}': {} (not saved, manual_override is set)", config.name, correction);
+            } else {
+                info!("'{}': {}", config.name, correction);
+            }
+        }
+        if !corrections.is_empty() && !config.manual_override {
+            corrected.push(config.name.clone());
+        }
+    }
+    if corrected.is_empty() {
+        return;
+    }
+
+    // correct what is on disk rather than writing the given entries, which may not match the registry
+    let mut registry = match read_registry_configs() {
+        Ok(registry) => registry,
+        Err(err) => {
+            warn!("unable to save corrections to the registry: {}", err);
+            return;
+        }
+    };
+    for name in &corrected {
+        if let Some(config) = registry.get_mut(name) {
+            config.correct();
+        }
+    }
+    if let Err(err) = patch_registry(&registry, &corrected) {
+        warn!("unable to save corrections to the registry: {}", err);
+    }
+}
+
+/// Writes the `type` and `scheme_path` corrected by [Service::correct] for each service in `names` into its
+/// table in the registry file. An `exec` resolved from `PATH` is only used in memory, so the file doesn't pin
+/// the binary to where it was found.
+///
+/// Only fields whose value changed are touched, so the rest of a hand-edited registry, including its comments
+/// and the order of its tables, is left as it was. The file is not written if nothing changed.
+fn patch_registry(configs: &HashMap<String, Service>, names: &[String]) -> Result<(), String> {
+    let path: &Path = Path::new(REGISTRY_PATH);
+    let toml_str = fs::read_to_string(path).map_err(|e| format!("Unable to read smregistry.toml: {}", e))?;
+    let mut document: DocumentMut = toml_str
+        .parse()
+        .map_err(|e| format!("Unable to parse smregistry.toml: {}", e))?;
+    if !patch_document(&mut document, configs, names) {
+        return Ok(());
+    }
+    fs::write(path, document.to_string()).map_err(|e| format!("Unable to write smregistry.toml: {}", e))
+}
+
+/// Patches the `[[service]]` tables of the services in `names` in a parsed registry, returning true if anything changed.
+fn patch_document(document: &mut DocumentMut, configs: &HashMap<String, Service>, names: &[String]) -> bool {
+    let Some(tables) = document.get_mut("service").and_then(|item| item.as_array_of_tables_mut()) else {
+        return false;
+    };
+    let mut changed = false;
+    for table in tables.iter_mut() {
+        let Some(name) = table.get("name").and_then(|name| name.as_str()).map(str::to_string) else {
+            continue;
+        };
+        let Some(config) = configs.get(&name).filter(|_| names.contains(&name)) else {
+            continue;
+        };
+        changed |= patch_field(table, "type", &config.r#type);
+        changed |= patch_field(table, "scheme_path", &config.scheme_path);
+    }
+    changed
+}
+
+/// Sets the string field `key` of a registry table to `new`, unless it already has that value, and returns
+/// true if it changed. A comment after the old value is kept.
+fn patch_field(table: &mut Table, key: &str, new: &str) -> bool {
+    match table.get_mut(key).and_then(|item| item.as_value_mut()) {
+        Some(old) if old.as_str() == Some(new) => return false,
+        Some(old) => {
+            let decor = old.decor().clone();
+            *old = new.into();
+            *old.decor_mut() = decor;
+        }
+        None => table[key] = value(new),
+    }
+    true
+}
+
+/// Reads the configuration of every service in the registry, keyed by service name.
+///
+/// Unlike [read_registry], this returns an error instead of panicking, since the registry may have been
+/// edited by hand while the service monitor is running.
+pub fn read_registry_configs() -> Result<HashMap<String, Service>, String> {
+    let registry = try_read_registry_file()?;
+    Ok(registry
+        .service
+        .into_iter()
+        .map(|config| (config.name.clone(), config))
+        .collect())
+}
+
+/// Returns the names of the registry fields that differ between `old` and `new`.
+pub fn changed_fields(old: &Service, new: &Service) -> Vec<String> {
+    // compare the fields as they are written in the registry
+    let (Ok(toml::Value::Table(old)), Ok(toml::Value::Table(new))) = (toml::Value::try_from(old), toml::Value::try_from(new)) else {
+        return vec![String::from("service")];
+    };
+    let mut fields: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect();
+    fields.sort();
+    fields.dedup();
+    fields
+}
+
+/// Reads and parses the whole registry file from disk.
+fn read_registry_file() -> Registry {
+    match try_read_registry_file() {
+        Ok(registry) => registry,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+/// Where the registry is read from and written to.
+// TODO: determine filepath (where will registry.toml be located?)
+// ! This filepath is just a temporary solution, as are those of the other files the service monitor keeps next to it.
+const REGISTRY_PATH: &str = "/usr/share/smregistry.toml";
+
+/// Reads and parses the whole registry file from disk, returning an error if that fails.
+fn try_read_registry_file() -> Result<Registry, String> {
+    let path: &Path = Path::new(REGISTRY_PATH);
     let mut file = match File::open(&path) {
-        Err(err) => panic!("Unable to open smregistry.toml: {}", err),
+        Err(err) => return Err(format!("Unable to open smregistry.toml: {}", err)),
         Ok(file) => file,
     };
 
     let mut toml_str: String = String::new();
     match file.read_to_string(&mut toml_str) {
-        Err(err) => panic!("Unable to read registry.toml as string: {}", err),
+        Err(err) => return Err(format!("Unable to read registry.toml as string: {}", err)),
         Ok(_) => {}
     };
 
@@ -96,35 +1013,7 @@ pub fn read_registry() -> HashMap<String, ServiceEntry> {
     // assert!(registry.service[0].args.is_empty());
     // assert!(registry.service[0].depends.is_empty());
 
-    // Sets up the services map for main.
-    let mut services: HashMap<String, ServiceEntry> = HashMap::new();
-    let registry: Registry = toml::from_str(&toml_str).expect("Unable to parse registry.toml");
-    for s in registry.service {
-        let new_entry = ServiceEntry {
-            config: s,
-            running: false,
-            pid: 0,
-            time_started: 0,
-            time_init: 0,
-            read_count: 0,
-            write_count: 0,
-            open_count: 0,
-            close_count: 0,
-            dup_count: 0,
-            error_count: 0,
-            total_reads: 0,
-            total_writes: 0,
-            total_opens: 0,
-            total_closes: 0,
-            total_dups: 0,
-            total_errors: 0,
-            last_response_time: 0,
-            message: String::new(),
-            message_time: 0,
-        };
-        services.insert(new_entry.config.name.clone(), new_entry);
-    }
-    return services;
+    toml::from_str(&toml_str).map_err(|err| format!("Unable to parse registry.toml: {}", err))
 }
 
 /// Writes a [HashMap] of service name [String]s mapped to [ServiceEntry] objects to the registry file on disk.
@@ -132,7 +1021,9 @@ pub fn read_registry() -> HashMap<String, ServiceEntry> {
 /// # Panics
 /// This function will panic if `registry.toml` is unable to be opened and written to.
 pub fn write_registry(registry: HashMap<String, ServiceEntry>) {
-    let path: &Path = Path::new("/usr/share/smregistry.toml"); //same as read_registry, this filepath is temporary.
+    // keep the global settings and targets as they are on disk
+    let Registry { monitor, target, .. } = read_registry_file();
+    let path: &Path = Path::new(REGISTRY_PATH);
     let mut file = match File::create(&path) {
         Err(err) => panic!("Unable to open smregistry.toml: {}", err),
         Ok(file) => file,
@@ -144,7 +1035,9 @@ pub fn write_registry(registry: HashMap<String, ServiceEntry>) {
         reconstructed.push(new_service);
     }
     let registry_struct = Registry {
+        monitor,
         service: reconstructed,
+        target,
     };
     let toml_str: String = toml::to_string(&registry_struct).unwrap();
     match file.write_all(&mut toml_str.as_bytes()) {
@@ -161,8 +1054,9 @@ pub fn view_entry(name: &str) -> Result<Option<TOMLMessage>, Option<TOMLMessage>
     let services = read_registry();
     if let Some(entry) = services.get(name) {
         let entry_string = format!(
-            "Service Name: {} \nType: {} \nArgs: {:?} \nManual Override: {} \nDepends: {:?} \nScheme Path: {}",
-            entry.config.name, entry.config.r#type, entry.config.args, entry.config.manual_override, entry.config.depends, entry.config.scheme_path
+            "Service Name: {} \nType: {} \nExecutable: {} \nArgs: {:?} \nEnv: {:?} \nWorking Directory: {} \nStdin: {:?} \nStdout: {:?} \nStderr: {:?} \nManual Override: {} \nDepends: {:?} \nScheme Path: {}",
+            entry.config.name, entry.config.r#type, entry.config.executable(), entry.config.args, entry.config.env, entry.config.cwd,
+            entry.config.stdin, entry.config.stdout, entry.config.stderr, entry.config.manual_override, entry.config.depends, entry.config.scheme_path
         );
         Ok(Some(TOMLMessage::String(entry_string)))
     } else {
@@ -183,36 +1077,18 @@ pub fn add_entry(
     depends: &Vec<String>,
 ) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
     let mut services = read_registry();
-    let new_entry = ServiceEntry {
-        config: Service {
-            name: name.to_string(),
-            r#type: r#type.to_string(),
-            args: args.to_vec(),
-            manual_override: manual_override,
-            depends: depends.to_vec(),
-            scheme_path: scheme_path.to_string(),
-        },
-        running: false,
-        pid: 0,
-        time_started: 0,
-        time_init: 0,
-        read_count: 0,
-        write_count: 0,
-        open_count: 0,
-        close_count: 0,
-        dup_count: 0,
-        error_count: 0,
-        total_reads: 0,
-        total_writes: 0,
-        total_opens: 0,
-        total_closes: 0,
-        total_dups: 0,
-        total_errors: 0,
-        last_response_time: 0,
-        message: String::new(),
-        message_time: 0,
+    // keep the launch settings of an existing entry, since they can't be given on the command line
+    let mut config = match services.remove(name) {
+        Some(old_entry) => old_entry.config,
+        None => Service::default(),
     };
-    services.insert(name.to_string(), new_entry);
+    config.name = name.to_string();
+    config.r#type = r#type.to_string();
+    config.args = args.to_vec();
+    config.manual_override = manual_override;
+    config.depends = depends.to_vec();
+    config.scheme_path = scheme_path.to_string();
+    services.insert(name.to_string(), ServiceEntry::new(config));
     write_registry(services);
 
     Ok(Some(TOMLMessage::String(format!("Successfully added service '{}' to registry", name))))
@@ -241,7 +1117,7 @@ pub fn edit_entry(
 ) -> Result<Option<TOMLMessage>, Option<TOMLMessage>> {
     let mut services = read_registry();
     if let Some(entry) = services.get_mut(name) {
-        if entry.running {
+        if entry.is_running() {
             warn!("Service is currently running");
         }
 
@@ -305,31 +1181,6 @@ pub fn edit_hash_entry(
             }
         }
 
-        // TODO: why are we cloning the entry we just edited?
-        let new_entry = ServiceEntry {
-            config: entry.config.clone(),
-            running: entry.running,
-            pid: entry.pid,
-            time_started: entry.time_started,
-            time_init: entry.time_init,
-            read_count: entry.read_count,
-            write_count: entry.write_count,
-            error_count: entry.error_count,
-            last_response_time: entry.last_response_time,
-            message: entry.message.clone(),
-            message_time: entry.message_time,
-            total_reads: entry.total_reads,
-            total_writes: entry.total_writes,
-            total_opens: entry.total_opens,
-            total_closes: entry.total_closes,
-            total_dups: entry.total_dups,
-            open_count: entry.open_count,
-            close_count: entry.close_count,
-            dup_count: entry.dup_count,
-            total_errors: entry.total_errors,
-        };
-
-        services.insert(name.to_string(), new_entry);
 
         // ! temp
         Ok(Some(TOMLMessage::String(format!("Successfully edited service '{}' in internal list", name))))
@@ -350,9 +1201,10 @@ pub fn rm_hash_entry(services: &mut HashMap<String, ServiceEntry>, name: &str) -
         //println!("Service is still present in registry,  from internal list");
     } else {
         if services.contains_key(name) {
-            let entry = services.get(name).unwrap();
-            if entry.running {
-                // todo: msg: "Running service has been removed from the registry. It will be removed from the internal list when the service is stopped."
+            let entry = services.get_mut(name).unwrap();
+            if entry.has_process() {
+                // the service is dropped from the internal list once it stops
+                entry.retired = true;
                 Err(Some(TOMLMessage::String(format!("Service: '{}' will be removed once it is stopped", name))))
                 //println!("Cannot remove an entry that is currently running");
             } else {
@@ -386,37 +1238,204 @@ pub fn add_hash_entry(
         Err(Some(TOMLMessage::String(format!("Unable to add '{}' to internal list: service already present", name))))
         //println!("Cannot add entry that is already present in internal list");
     } else {
-        let new_entry = ServiceEntry {
-            config: Service {
-                name: name.to_string(),
-                r#type: r#type.to_string(),
-                args: args.to_vec(),
-                manual_override: manual_override,
-                depends: depends.to_vec(),
-                scheme_path: scheme_path.to_string(),
-            },
-            running: false,
-            pid: 0,
-            time_started: 0,
-            time_init: 0,
-            read_count: 0,
-            write_count: 0,
-            open_count: 0,
-            close_count: 0,
-            dup_count: 0,
-            error_count: 0,
-            total_reads: 0,
-            total_writes: 0,
-            total_opens: 0,
-            total_closes: 0,
-            total_dups: 0,
-            total_errors: 0,
-            last_response_time: 0,
-            message: String::new(),
-            message_time: 0,
-        };
+        let new_entry = ServiceEntry::new(Service {
+            name: name.to_string(),
+            r#type: r#type.to_string(),
+            args: args.to_vec(),
+            manual_override: manual_override,
+            depends: depends.to_vec(),
+            scheme_path: scheme_path.to_string(),
+            ..Default::default()
+        });
         services.insert(name.to_string(), new_entry);
         Ok(Some(TOMLMessage::String(format!("Successfully added service '{}' to internal list", name))))
     }
 }
 //add old, add new, rm, view
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str) -> Service {
+        Service {
+            name: name.to_string(),
+            r#type: "daemon".to_string(),
+            scheme_path: format!("/scheme/{}", name),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unchanged_service_has_no_changed_fields() {
+        assert!(changed_fields(&service("a"), &service("a")).is_empty());
+    }
+
+    #[test]
+    fn changed_fields_are_named_as_in_the_registry() {
+        let old = service("a");
+        let mut new = service("a");
+        new.args = vec!["1".to_string()];
+        new.timeout_ms = 1000;
+        new.autostart = false;
+        assert_eq!(changed_fields(&old, &new), vec!["args", "autostart", "timeout_ms"]);
+    }
+
+    #[test]
+    fn probes_are_read_from_service_tables() {
+        let config: Service = toml::from_str(
+            r#"
+            name = "a"
+            type = "daemon"
+            args = []
+            manual_override = false
+            depends = []
+            scheme_path = "/scheme/a"
+            probe_failure_threshold = 2
+
+            [[probe]]
+            kind = "read"
+            path = "seed"
+            len = 8
+
+            [[probe]]
+            kind = "fstat"
+            timeout_ms = 100
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.probe.len(), 2);
+        assert_eq!(config.probe[0].to_string(), "read of 8 bytes from 'seed'");
+        assert_eq!(config.probe[1].kind, ProbeKind::Fstat);
+        assert_eq!(config.probe[1].timeout_ms, Some(100));
+        assert_eq!(config.probe_interval_ms, default_probe_interval());
+        assert_eq!(config.probe_failure_threshold, 2);
+    }
+
+    #[test]
+    fn correct_normalizes_type_and_defaults_scheme_path() {
+        let mut config = service("a");
+        config.r#type = " Unmanaged".to_string();
+        config.exec = "/bin/a".to_string();
+        assert_eq!(config.correct().len(), 1);
+        assert_eq!(config.r#type, "unmanaged");
+
+        let mut config = service("a");
+        config.r#type = "driver".to_string();
+        config.scheme_path = String::new();
+        config.exec = "/bin/a".to_string();
+        assert_eq!(config.correct().len(), 2);
+        assert_eq!(config.r#type, "daemon");
+        assert_eq!(config.scheme_path, "/scheme/a");
+        assert!(config.correct().is_empty());
+    }
+
+    #[test]
+    fn correct_resolves_exec_from_path() {
+        let dir = std::env::temp_dir().join(format!("sm-correct-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("sm-test-daemon"), b"").unwrap();
+        let old_path = std::env::var("PATH").unwrap_or_default();
+        std::env::set_var("PATH", format!("{}:{}", dir.display(), old_path));
+
+        let mut config = service("sm-test-daemon");
+        let corrections = config.correct();
+        std::env::set_var("PATH", old_path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(corrections.len(), 1);
+        assert_eq!(config.exec, dir.join("sm-test-daemon").to_string_lossy());
+    }
+
+    #[test]
+    fn patch_keeps_comments_and_leaves_exec_unresolved() {
+        let mut document: DocumentMut = concat!(
+            "# services\n",
+            "[[service]]\n",
+            "name = \"a\"\n",
+            "type = \"Daemon\" # kept\n",
+            "scheme_path = \"\"\n",
+            "\n",
+            "[[service]]\n",
+            "name = \"b\"\n",
+            "type = \"driver\"\n",
+        )
+        .parse()
+        .unwrap();
+        let mut a = service("a");
+        a.exec = "/usr/bin/a".to_string();
+        let mut b = service("b");
+        b.r#type = "driver".to_string();
+        let configs = HashMap::from([("a".to_string(), a), ("b".to_string(), b)]);
+
+        assert!(patch_document(&mut document, &configs, &["a".to_string()]));
+        assert_eq!(
+            document.to_string(),
+            concat!(
+                "# services\n",
+                "[[service]]\n",
+                "name = \"a\"\n",
+                "type = \"daemon\" # kept\n",
+                "scheme_path = \"/scheme/a\"\n",
+                "\n",
+                "[[service]]\n",
+                "name = \"b\"\n",
+                "type = \"driver\"\n",
+            )
+        );
+        assert!(!patch_document(&mut document, &configs, &["a".to_string()]));
+    }
+
+    #[test]
+    fn unknown_exit_status_is_not_a_failure() {
+        let exit = |code, signal| ExitInfo { time: 0, code, signal };
+        assert!(!exit(Some(0), None).failed());
+        assert!(exit(Some(1), None).failed());
+        assert!(exit(None, Some(9)).failed());
+        assert!(!exit(None, None).failed());
+    }
+
+    #[test]
+    fn restart_policies_after_an_exit() {
+        let policies = [RestartPolicy::Never, RestartPolicy::OnFailure, RestartPolicy::Always, RestartPolicy::MonitorOnly];
+        let after_failure: Vec<bool> = policies.iter().map(|policy| policy.restarts_after_exit(true)).collect();
+        let after_stop: Vec<bool> = policies.iter().map(|policy| policy.restarts_after_exit(false)).collect();
+        assert_eq!(after_failure, vec![false, true, true, false]);
+        assert_eq!(after_stop, vec![false, false, true, false]);
+    }
+
+    #[test]
+    fn restart_policies_are_kebab_case() {
+        let mut config = service("a");
+        config.restart = RestartPolicy::MonitorOnly;
+        let toml_str = toml::to_string(&config).unwrap();
+        assert!(toml_str.contains("restart = \"monitor-only\""));
+        let parsed: Service = toml::from_str(&toml_str).unwrap();
+        assert_eq!(parsed.restart, RestartPolicy::MonitorOnly);
+    }
+
+    #[test]
+    fn drained_output_keeps_its_start_and_reads_to_the_end() {
+        use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+        /// A pipe holding `left` bytes that counts how many were read from it.
+        struct Pipe {
+            left: usize,
+            read: Arc<AtomicUsize>,
+        }
+        impl Read for Pipe {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let size = buf.len().min(self.left);
+                buf[..size].fill(b'x');
+                self.left -= size;
+                self.read.fetch_add(size, Ordering::Relaxed);
+                Ok(size)
+            }
+        }
+
+        let read = Arc::new(AtomicUsize::new(0));
+        let output = drain(Pipe { left: 100_000, read: read.clone() }).join().unwrap();
+        assert_eq!(output, vec![b'x'; HEALTH_OUTPUT_LIMIT as usize]);
+        assert_eq!(read.load(Ordering::Relaxed), 100_000);
+    }
+}
@@ -1,81 +1,223 @@
+use hashbrown::HashMap;
+use log::warn;
 use redox_scheme::Scheme;
-use shared::{RegistryCommand, SMCommand, CommandResponse};
-use syscall::{error::*, MODE_CHR};
+use shared::{RegistryCommand, SMCommand, CommandResponse, ServiceEvent, FRAME_END};
+use syscall::{error::*, EventFlags, EVENT_READ, MODE_CHR};
 
 //use std::fs::File;
 // Ty is to leave room for other types of monitor schemes
 // maybe an int or enum for the command, string buffer for service name?
 
 pub struct SMScheme {
-    pub cmd: Option<SMCommand>,
+    /// The session of every open handle, keyed by handle id.
+    sessions: HashMap<usize, Session>,
+    next_id: usize,
+    /// Handles that became readable and have not been notified through fevent yet.
+    readable: Vec<usize>,
+    /// The most events a watch handle holds before its oldest unread ones are dropped.
+    watch_capacity: usize,
+}
+
+/// The state of a single handle opened on the service monitor's scheme, so that several
+/// clients (e.g. the CLI and the GUI) can send commands at the same time.
+///
+/// A handle opened with the path "watch" is a watch handle instead. Commands can't be written to it,
+/// and reading it returns the stream of service events that happened since it was opened.
+struct Session {
+    /// The command written to this handle that has not been run yet.
+    cmd: Option<SMCommand>,
+    /// The response to the last command, or the unread part of the event stream of a watch handle.
     response_buffer: Vec<u8>,
     read_index: usize,
+    watch: bool,
 }
 
-impl SMScheme {
-    /// Construct an [SMScheme] with default fields. 
-    pub fn new() -> SMScheme {
-        SMScheme {
+impl Session {
+    fn new(watch: bool) -> Session {
+        Session {
             cmd: None,
             response_buffer: Vec::new(),
             read_index: 0,
+            watch,
         }
     }
 
-    /// Write a [CommandResponse] to the response buffer.
+    /// Returns true if this handle has bytes waiting to be read.
+    fn has_unread(&self) -> bool {
+        self.read_index < self.response_buffer.len()
+    }
+}
+
+/// Drops the oldest whole events from a watch handle's unread `stream` so at most `capacity` are left,
+/// returning how many were dropped. If `mid_frame` is set, the client has read the start of the first
+/// event, so the rest of it is kept.
+fn trim_stream(stream: &mut Vec<u8>, mid_frame: bool, capacity: usize) -> usize {
+    let start = if mid_frame {
+        match stream.iter().position(|byte| *byte == FRAME_END) {
+            Some(end) => end + 1,
+            None => return 0,
+        }
+    } else {
+        0
+    };
+    let ends: Vec<usize> = stream[start..]
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == FRAME_END)
+        .map(|(i, _)| start + i)
+        .collect();
+    if ends.len() <= capacity {
+        return 0;
+    }
+    let dropped = ends.len() - capacity;
+    stream.drain(start..=ends[dropped - 1]);
+    dropped
+}
+
+impl SMScheme {
+    /// Construct an [SMScheme] with default fields, whose watch handles each hold up to `watch_capacity` unread events.
+    pub fn new(watch_capacity: usize) -> SMScheme {
+        SMScheme {
+            sessions: HashMap::new(),
+            next_id: 0,
+            readable: Vec::new(),
+            watch_capacity,
+        }
+    }
+
+    /// Takes the next command waiting to be run, along with the id of the handle it was written to.
+    /// The response to the command should be written back to the same handle with [SMScheme::write_response].
+    pub fn take_command(&mut self) -> Option<(usize, SMCommand)> {
+        let id = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.cmd.is_some())
+            .map(|(id, _)| *id)
+            .min()?;
+        let cmd = self.sessions.get_mut(&id)?.cmd.take()?;
+        Some((id, cmd))
+    }
+
+    /// Write a [CommandResponse] to the response buffer of the handle `id`.
     /// This method does not take ownership of `response`.
-    pub fn write_response(&mut self, response: &CommandResponse) -> Result<usize, String> {
+    pub fn write_response(&mut self, id: usize, response: &CommandResponse) -> Result<usize, String> {
         toml::to_string(response)
             .map_err(|e| format!("Failed to encode SMCommand into string: {}", e))
             .map(|s| { s.into_bytes() })
             .and_then(|buf| {
-                self.write_bytes(&buf)
+                self.write_bytes(id, &buf)
             })
     }
 
-    /// Write bytes to the response buffer. If the response buffer has content already in it,
+    /// Write bytes to the response buffer of the handle `id`. If the response buffer has content already in it,
     /// it will be overwritten.
-    fn write_bytes(&mut self, buf: &[u8]) -> Result<usize, String> {
-        self.response_buffer = buf.to_vec();
-        self.read_index = 0;
+    fn write_bytes(&mut self, id: usize, buf: &[u8]) -> Result<usize, String> {
+        let session = self
+            .sessions
+            .get_mut(&id)
+            .ok_or_else(|| format!("Handle {} was closed before its response was written", id))?;
+        session.response_buffer = buf.to_vec();
+        session.read_index = 0;
+        if !buf.is_empty() {
+            self.readable.push(id);
+        }
         Ok(buf.len())
     }
+
+    /// Appends `events` to the stream of every watch handle. A handle that already holds `watch_capacity`
+    /// unread events loses the oldest ones, so a client that stops reading can't grow its stream without bound.
+    pub fn publish(&mut self, events: &[ServiceEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        let mut frames: Vec<u8> = Vec::new();
+        for event in events {
+            match event.encode_frame() {
+                Ok(frame) => frames.extend_from_slice(&frame),
+                Err(e) => warn!("{}", e),
+            }
+        }
+        for (id, session) in self.sessions.iter_mut().filter(|(_, session)| session.watch) {
+            // drop what was already read so the stream doesn't grow forever
+            let mid_frame = session.read_index > 0 && session.response_buffer[session.read_index - 1] != FRAME_END;
+            session.response_buffer.drain(..session.read_index);
+            session.read_index = 0;
+            session.response_buffer.extend_from_slice(&frames);
+            let dropped = trim_stream(&mut session.response_buffer, mid_frame, self.watch_capacity);
+            if dropped > 0 {
+                warn!("watch handle {} is not keeping up, dropped {} events", id, dropped);
+            }
+            self.readable.push(*id);
+        }
+    }
+
+    /// Takes the handles that became readable since this was last called, so their readiness can be posted
+    /// to anyone waiting on them through fevent.
+    pub fn take_readable(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.readable)
+    }
+
+    /// Creates a session for a new handle and returns the handle's id.
+    fn new_session(&mut self, watch: bool) -> usize {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.sessions.insert(id, Session::new(watch));
+        id
+    }
 }
 
 impl Scheme for SMScheme {
-    fn open(&mut self, _path: &str, _flags: usize, _uid: u32, _gid: u32) -> Result<usize> {
-        Ok(0)
+    fn open(&mut self, path: &str, _flags: usize, _uid: u32, _gid: u32) -> Result<usize> {
+        match path.trim_matches('/') {
+            "" => Ok(self.new_session(false)),
+            "watch" => Ok(self.new_session(true)),
+            _ => Err(Error::new(ENOENT)),
+        }
     }
 
-    fn dup(&mut self, _file: usize, buf: &[u8]) -> Result<usize> {
-        if !buf.is_empty() {
-            return Err(Error::new(EINVAL));
+    fn dup(&mut self, file: usize, buf: &[u8]) -> Result<usize> {
+        if !self.sessions.contains_key(&file) {
+            return Err(Error::new(EBADF));
+        }
+        match buf {
+            b"" => Ok(self.new_session(false)),
+            b"watch" => Ok(self.new_session(true)),
+            _ => Err(Error::new(EINVAL)),
         }
-
-        Ok(0)
     }
 
-    fn read(&mut self, _file: usize, buf: &mut [u8], _offset: u64, _flags: u32) -> Result<usize> {
-        if self.read_index != self.response_buffer.len() {
+    fn read(&mut self, file: usize, buf: &mut [u8], _offset: u64, _flags: u32) -> Result<usize> {
+        let session = self.sessions.get_mut(&file).ok_or(Error::new(EBADF))?;
+        if session.read_index != session.response_buffer.len() {
             let buf_len = buf.len();
-            let res_len = self.response_buffer.len() - self.read_index;
+            let res_len = session.response_buffer.len() - session.read_index;
             let size = std::cmp::min(buf_len, res_len);
             if buf_len < res_len {
                 buf.copy_from_slice(
-                    &self.response_buffer[self.read_index..(size + self.read_index)],
+                    &session.response_buffer[session.read_index..(size + session.read_index)],
                 );
             } else {
-                buf[..size].copy_from_slice(&self.response_buffer[self.read_index..]);
+                buf[..size].copy_from_slice(&session.response_buffer[session.read_index..]);
             }
-            self.read_index = self.read_index + size;
+            session.read_index = session.read_index + size;
             Ok(size)
+        } else if session.watch {
+            // the service monitor can't block, so wait for EVENT_READ through fevent before reading again
+            Err(Error::new(EAGAIN))
         } else {
             Ok(0)
         }
     }
 
-    fn write(&mut self, _file: usize, buffer: &[u8], _offset: u64, _flags: u32) -> Result<usize> {
-        self.cmd = match SMCommand::decode(buffer) {
+    fn write(&mut self, file: usize, buffer: &[u8], _offset: u64, _flags: u32) -> Result<usize> {
+        let session = self.sessions.get_mut(&file).ok_or(Error::new(EBADF))?;
+        if session.watch {
+            return Err(Error::new(EBADF));
+        }
+        // a new command replaces the previous response
+        session.response_buffer.clear();
+        session.read_index = 0;
+        session.cmd = match SMCommand::decode(buffer) {
             Ok(cmd) => Some(cmd),
             Err(_) => None,
         };
@@ -85,6 +227,15 @@ impl Scheme for SMScheme {
     fn fcntl(&mut self, _id: usize, _cmd: usize, _arg: usize) -> Result<usize> {
         Ok(0)
     }
+
+    fn fevent(&mut self, id: usize, _flags: EventFlags) -> Result<EventFlags> {
+        let session = self.sessions.get(&id).ok_or(Error::new(EBADF))?;
+        if session.has_unread() {
+            Ok(EVENT_READ)
+        } else {
+            Ok(EventFlags::empty())
+        }
+    }
     fn fsize(&mut self, _id: usize) -> Result<u64> {
         Ok(0)
     }
@@ -106,7 +257,9 @@ impl Scheme for SMScheme {
     }
 
     /// Close the file `number`
-    fn close(&mut self, _file: usize) -> Result<usize> {
+    fn close(&mut self, file: usize) -> Result<usize> {
+        // drop the handle's pending command and response along with it
+        self.sessions.remove(&file).ok_or(Error::new(EBADF))?;
         Ok(0)
     }
     fn fstat(&mut self, _: usize, stat: &mut syscall::Stat) -> Result<usize> {
@@ -119,3 +272,95 @@ impl Scheme for SMScheme {
         Ok(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream of whole frames, each holding its index as its only character.
+    fn frames(count: u8) -> Vec<u8> {
+        (b'0'..b'0' + count).flat_map(|i| [i, FRAME_END]).collect()
+    }
+
+    #[test]
+    fn stream_within_capacity_is_kept() {
+        let mut stream = frames(3);
+        assert_eq!(trim_stream(&mut stream, false, 3), 0);
+        assert_eq!(stream, frames(3));
+    }
+
+    #[test]
+    fn oldest_frames_over_capacity_are_dropped() {
+        let mut stream = frames(5);
+        assert_eq!(trim_stream(&mut stream, false, 2), 3);
+        assert_eq!(stream, vec![b'3', FRAME_END, b'4', FRAME_END]);
+    }
+
+    #[test]
+    fn trim_keeps_the_rest_of_a_partly_read_frame() {
+        // the client has read the start of the first frame, so only its last byte and end are left
+        let mut stream = vec![b'a', FRAME_END];
+        stream.extend(frames(3));
+        assert_eq!(trim_stream(&mut stream, true, 1), 2);
+        assert_eq!(stream, vec![b'a', FRAME_END, b'2', FRAME_END]);
+    }
+
+    #[test]
+    fn trim_without_a_whole_frame_keeps_everything() {
+        let mut stream = vec![b'a', b'b'];
+        assert_eq!(trim_stream(&mut stream, true, 0), 0);
+        assert_eq!(stream, vec![b'a', b'b']);
+    }
+
+    /// Writes a command to start `name` to the handle `id`.
+    fn send(scheme: &mut SMScheme, id: usize, name: &str) {
+        let cmd = SMCommand::Start { service_name: name.to_string(), force: false };
+        scheme.write(id, &cmd.encode().unwrap(), 0, 0).unwrap();
+    }
+
+    /// Takes every waiting command, returning the handle and service name of each in the order they are taken.
+    fn take_all(scheme: &mut SMScheme) -> Vec<(usize, String)> {
+        std::iter::from_fn(|| scheme.take_command())
+            .map(|(id, cmd)| match cmd {
+                SMCommand::Start { service_name, .. } => (id, service_name),
+                _ => panic!("unexpected command"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn commands_are_taken_oldest_handle_first() {
+        let mut scheme = SMScheme::new(4);
+        let ids: Vec<usize> = (0..3).map(|_| scheme.open("", 0, 0, 0).unwrap()).collect();
+        send(&mut scheme, ids[2], "c");
+        send(&mut scheme, ids[0], "a");
+        send(&mut scheme, ids[1], "b");
+        assert_eq!(take_all(&mut scheme), vec![(ids[0], "a".to_string()), (ids[1], "b".to_string()), (ids[2], "c".to_string())]);
+        assert!(scheme.take_command().is_none());
+    }
+
+    #[test]
+    fn each_command_is_taken_once() {
+        let mut scheme = SMScheme::new(4);
+        let first = scheme.open("", 0, 0, 0).unwrap();
+        let second = scheme.open("", 0, 0, 0).unwrap();
+        send(&mut scheme, second, "b");
+        assert_eq!(take_all(&mut scheme), vec![(second, "b".to_string())]);
+        // a command written while another handle's is being run is taken next
+        send(&mut scheme, first, "a");
+        send(&mut scheme, second, "c");
+        assert_eq!(take_all(&mut scheme), vec![(first, "a".to_string()), (second, "c".to_string())]);
+    }
+
+    #[test]
+    fn closed_and_watch_handles_have_no_commands() {
+        let mut scheme = SMScheme::new(4);
+        let closed = scheme.open("", 0, 0, 0).unwrap();
+        let watch = scheme.open("watch", 0, 0, 0).unwrap();
+        send(&mut scheme, closed, "a");
+        scheme.close(closed).unwrap();
+        let cmd = SMCommand::Start { service_name: "b".to_string(), force: false };
+        assert!(scheme.write(watch, &cmd.encode().unwrap(), 0, 0).is_err());
+        assert!(scheme.take_command().is_none());
+    }
+}
@@ -0,0 +1,175 @@
+use hashbrown::HashMap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use shared::ServiceState;
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::registry::ServiceEntry;
+
+/// Where the processes the service monitor owns are recorded, so they can be adopted again if it restarts.
+// a snapshot left over from before a reboot is harmless, since every process in it is probed before it is adopted.
+const SNAPSHOT_PATH: &str = "/usr/share/smruntime.toml";
+
+/// The contents of the snapshot file.
+#[derive(Serialize, Deserialize, Default)]
+struct SnapshotFile {
+    #[serde(default)]
+    service: BTreeMap<String, SavedProcess>,
+}
+
+/// A process the previous service monitor owned.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedProcess {
+    /// The pid of the service's process, or 0 if it is unknown.
+    pub pid: usize,
+    pub scheme_path: String,
+    /// The timestamp, in milliseconds from the Unix epoch, that the service was started.
+    pub time_started: i64,
+    /// The timestamp, in milliseconds from the Unix epoch, that the service was initialized.
+    pub time_init: i64,
+    pub state: ServiceState,
+}
+
+impl SavedProcess {
+    fn from_entry(service: &ServiceEntry) -> SavedProcess {
+        SavedProcess {
+            pid: service.pid,
+            scheme_path: service.config.scheme_path.clone(),
+            time_started: service.time_started,
+            time_init: service.time_init,
+            state: service.state,
+        }
+    }
+}
+
+/// Keeps a record of every service process the service monitor is tracking.
+///
+/// The record only says what the previous service monitor believed; a process it lists may have exited
+/// since, so it must be probed before being adopted.
+pub struct RuntimeSnapshot {
+    /// What was last written to the snapshot file, so an unchanged snapshot is not written again.
+    saved: String,
+    /// True until the first save, which drops the processes from the previous service monitor that were not adopted.
+    stale: bool,
+}
+
+impl RuntimeSnapshot {
+    /// Reads the snapshot left by the previous service monitor, returning the processes it recorded.
+    /// A missing or unreadable file records no processes.
+    pub fn load() -> (RuntimeSnapshot, BTreeMap<String, SavedProcess>) {
+        Self::load_from(Path::new(SNAPSHOT_PATH))
+    }
+
+    fn load_from(path: &Path) -> (RuntimeSnapshot, BTreeMap<String, SavedProcess>) {
+        let saved = match fs::read_to_string(path) {
+            Ok(saved) => saved,
+            Err(err) => {
+                info!("no runtime snapshot loaded from {}: {}", path.display(), err);
+                return (RuntimeSnapshot { saved: String::new(), stale: true }, BTreeMap::new());
+            }
+        };
+        let processes = match toml::from_str::<SnapshotFile>(&saved) {
+            Ok(file) => file.service,
+            Err(err) => {
+                warn!("ignoring unreadable runtime snapshot {}: {}", path.display(), err);
+                BTreeMap::new()
+            }
+        };
+        (RuntimeSnapshot { saved, stale: true }, processes)
+    }
+
+    /// Records every service that has a process, if a service was started, stopped, exited, restarted,
+    /// or adopted since the snapshot was last written.
+    pub fn save(&mut self, services: &mut HashMap<String, ServiceEntry>) {
+        self.save_to(Path::new(SNAPSHOT_PATH), services);
+    }
+
+    fn save_to(&mut self, path: &Path, services: &mut HashMap<String, ServiceEntry>) {
+        if !self.stale && !services.values().any(|service| service.snapshot_dirty) {
+            return;
+        }
+        let file = SnapshotFile {
+            service: services
+                .iter()
+                .filter(|(_, service)| service.has_process())
+                .map(|(name, service)| (name.clone(), SavedProcess::from_entry(service)))
+                .collect(),
+        };
+        let toml_str = match toml::to_string(&file) {
+            Ok(toml_str) => toml_str,
+            Err(err) => {
+                warn!("unable to encode runtime snapshot: {}", err);
+                return;
+            }
+        };
+        if toml_str != self.saved {
+            if let Err(err) = fs::write(path, &toml_str) {
+                // left dirty so the write is tried again
+                warn!("unable to write runtime snapshot to {}: {}", path.display(), err);
+                return;
+            }
+            self.saved = toml_str;
+        }
+        self.stale = false;
+        for service in services.values_mut() {
+            service.snapshot_dirty = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Service;
+    use std::path::PathBuf;
+
+    /// A snapshot file of its own for each test, since tests run in parallel.
+    fn snapshot_path(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("smruntime-{}-{}.toml", std::process::id(), test));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn running(name: &str, pid: usize) -> (String, ServiceEntry) {
+        let mut service = ServiceEntry::new(Service { name: name.to_string(), ..Default::default() });
+        service.pid = pid;
+        service.state = ServiceState::Running;
+        (name.to_string(), service)
+    }
+
+    #[test]
+    fn stale_snapshot_is_rewritten_without_changes() {
+        let path = snapshot_path("stale");
+        let mut services = HashMap::from([running("a", 10), running("b", 11)]);
+        RuntimeSnapshot::load_from(&path).0.save_to(&path, &mut services);
+
+        // only "a" was adopted by the next service monitor, so "b" is dropped on its first save
+        let (mut snapshot, processes) = RuntimeSnapshot::load_from(&path);
+        assert_eq!(processes.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+        let mut services = HashMap::from([running("a", 10)]);
+        snapshot.save_to(&path, &mut services);
+        let (_, processes) = RuntimeSnapshot::load_from(&path);
+        assert_eq!(processes.keys().collect::<Vec<_>>(), vec!["a"]);
+        assert_eq!(processes["a"].pid, 10);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn fresh_snapshot_is_only_rewritten_when_dirty() {
+        let path = snapshot_path("fresh");
+        let mut services = HashMap::from([running("a", 10)]);
+        let (mut snapshot, _) = RuntimeSnapshot::load_from(&path);
+        snapshot.save_to(&path, &mut services);
+
+        services.get_mut("a").unwrap().pid = 20;
+        snapshot.save_to(&path, &mut services);
+        assert_eq!(RuntimeSnapshot::load_from(&path).1["a"].pid, 10);
+
+        let service = services.get_mut("a").unwrap();
+        service.snapshot_dirty = true;
+        snapshot.save_to(&path, &mut services);
+        assert_eq!(RuntimeSnapshot::load_from(&path).1["a"].pid, 20);
+        assert!(!services["a"].snapshot_dirty);
+        let _ = fs::remove_file(&path);
+    }
+}
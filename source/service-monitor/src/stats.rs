@@ -0,0 +1,193 @@
+use hashbrown::HashMap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::registry::{ExitInfo, ServiceEntry};
+
+/// Where long-term statistics are kept between runs of the service monitor.
+const STATS_PATH: &str = "/usr/share/smstats.toml";
+
+/// The contents of the stats file.
+#[derive(Serialize, Deserialize, Default)]
+struct StatsFile {
+    #[serde(default)]
+    service: BTreeMap<String, SavedStats>,
+}
+
+/// The statistics of one service that outlive the service monitor.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+struct SavedStats {
+    total_reads: u64,
+    total_writes: u64,
+    total_opens: u64,
+    total_closes: u64,
+    total_dups: u64,
+    total_errors: u64,
+    restart_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_exit: Option<ExitInfo>,
+}
+
+impl SavedStats {
+    fn from_entry(service: &ServiceEntry) -> SavedStats {
+        SavedStats {
+            total_reads: service.total_reads,
+            total_writes: service.total_writes,
+            total_opens: service.total_opens,
+            total_closes: service.total_closes,
+            total_dups: service.total_dups,
+            total_errors: service.total_errors,
+            restart_count: service.restart_count,
+            last_exit: service.last_exit.clone(),
+        }
+    }
+
+    fn apply(self, service: &mut ServiceEntry) {
+        service.total_reads = self.total_reads;
+        service.total_writes = self.total_writes;
+        service.total_opens = self.total_opens;
+        service.total_closes = self.total_closes;
+        service.total_dups = self.total_dups;
+        service.total_errors = self.total_errors;
+        service.restart_count = self.restart_count;
+        service.last_exit = self.last_exit;
+    }
+}
+
+/// Keeps the lifetime totals, restart count, and last exit of every service in a file so they survive
+/// the service monitor restarting.
+///
+/// Totals are saved as of the last time each service's short-term stats were folded into them,
+/// which happens when it is cleared, stopped, exits, or is restarted.
+pub struct StatsStore {
+    /// What was last written to the stats file, so stats that changed back are not written again.
+    saved: String,
+}
+
+impl StatsStore {
+    /// Reads the stats file and applies the saved stats to the matching services.
+    /// A missing or unreadable file is treated as empty.
+    pub fn load(services: &mut HashMap<String, ServiceEntry>) -> StatsStore {
+        let path = Path::new(STATS_PATH);
+        let saved = match fs::read_to_string(path) {
+            Ok(saved) => saved,
+            Err(err) => {
+                info!("no saved stats loaded from {}: {}", STATS_PATH, err);
+                return StatsStore { saved: String::new() };
+            }
+        };
+        if let Err(err) = apply(&saved, services) {
+            warn!("ignoring unreadable stats file {}: {}", STATS_PATH, err);
+        }
+        StatsStore { saved }
+    }
+
+    /// Writes the stats of every service to the stats file if any service's stats changed since they were last written.
+    pub fn save(&mut self, services: &mut HashMap<String, ServiceEntry>) {
+        if !services.values().any(|service| service.stats_dirty) {
+            return;
+        }
+        let toml_str = match encode(services) {
+            Ok(toml_str) => toml_str,
+            Err(err) => {
+                warn!("unable to encode stats: {}", err);
+                return;
+            }
+        };
+        if toml_str != self.saved {
+            if let Err(err) = fs::write(STATS_PATH, &toml_str) {
+                // left dirty so the write is tried again
+                warn!("unable to write stats to {}: {}", STATS_PATH, err);
+                return;
+            }
+            self.saved = toml_str;
+        }
+        for service in services.values_mut() {
+            service.stats_dirty = false;
+        }
+    }
+}
+
+/// Encodes the stats of every service as the contents of the stats file.
+fn encode(services: &HashMap<String, ServiceEntry>) -> Result<String, toml::ser::Error> {
+    let file = StatsFile {
+        service: services
+            .iter()
+            .map(|(name, service)| (name.clone(), SavedStats::from_entry(service)))
+            .collect(),
+    };
+    toml::to_string(&file)
+}
+
+/// Applies the stats in the contents of a stats file to the matching services.
+fn apply(saved: &str, services: &mut HashMap<String, ServiceEntry>) -> Result<(), toml::de::Error> {
+    let file: StatsFile = toml::from_str(saved)?;
+    for (name, stats) in file.service {
+        if let Some(service) = services.get_mut(&name) {
+            stats.apply(service);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Service;
+
+    fn services(names: &[&str]) -> HashMap<String, ServiceEntry> {
+        names
+            .iter()
+            .map(|name| {
+                let config = Service { name: name.to_string(), ..Default::default() };
+                (name.to_string(), ServiceEntry::new(config))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn saved_stats_round_trip() {
+        let mut before = services(&["a", "b"]);
+        let a = before.get_mut("a").unwrap();
+        a.total_reads = 10;
+        a.total_writes = 20;
+        a.total_opens = 3;
+        a.total_closes = 2;
+        a.total_dups = 1;
+        a.total_errors = 4;
+        a.restart_count = 5;
+        a.last_exit = Some(ExitInfo { code: Some(1), signal: None, time: 1234 });
+
+        let saved = encode(&before).unwrap();
+        let mut after = services(&["a", "b"]);
+        apply(&saved, &mut after).unwrap();
+        for name in ["a", "b"] {
+            assert_eq!(SavedStats::from_entry(&after[name]), SavedStats::from_entry(&before[name]));
+        }
+        assert_eq!(encode(&after).unwrap(), saved);
+    }
+
+    #[test]
+    fn stats_of_unknown_services_are_ignored() {
+        let saved = encode(&services(&["gone"])).unwrap();
+        let mut after = services(&["a"]);
+        apply(&saved, &mut after).unwrap();
+        assert_eq!(after["a"].restart_count, 0);
+    }
+
+    #[test]
+    fn counts_are_folded_into_saved_totals() {
+        let mut before = services(&["a"]);
+        let a = before.get_mut("a").unwrap();
+        a.total_reads = 10;
+        a.read_count = 5;
+        a.error_count = 2;
+        a.fold_counts();
+        assert_eq!((a.read_count, a.error_count), (0, 0));
+
+        let mut after = services(&["a"]);
+        apply(&encode(&before).unwrap(), &mut after).unwrap();
+        assert_eq!((after["a"].total_reads, after["a"].total_errors), (15, 2));
+    }
+}
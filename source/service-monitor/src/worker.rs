@@ -0,0 +1,219 @@
+use hashbrown::HashMap;
+use libredox::{errno::*, error::*, flag::*};
+use log::warn;
+
+use crate::registry::{Probe, ProbeKind};
+
+use std::{
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// A thread that does all I/O on one service's scheme, so that a service that stops responding
+/// only blocks its own worker and never the service monitor.
+///
+/// The worker keeps the service's scheme and subschemes open between calls. A worker that times out
+/// is stuck inside the service's scheme; its thread exits once the call returns, which happens when the
+/// service is killed. Until then every call fails right away with `EBUSY` instead of queueing behind it,
+/// so a service that is never killed can keep its one worker rather than strand a new thread on each call.
+pub struct ServiceWorker {
+    jobs: Sender<Job>,
+    /// Where the result of the call that timed out will arrive, if it hasn't yet.
+    stuck: Mutex<Option<Receiver<Result<(usize, Vec<u8>)>>>>,
+}
+
+/// An operation on a subscheme. An empty subscheme name refers to the service's main scheme.
+enum Op {
+    /// Only opens the main scheme, if it is not open already.
+    Open,
+    Read { subscheme: String, len: usize },
+    Write { subscheme: String, data: Vec<u8> },
+    /// Runs a probe on its own file descriptor, which is closed afterwards.
+    Probe(Probe),
+}
+
+struct Job {
+    op: Op,
+    /// Where the result is sent, along with the bytes read if this was a read.
+    reply: Sender<Result<(usize, Vec<u8>)>>,
+}
+
+impl ServiceWorker {
+    /// Starts a worker for the service `name`, whose scheme is at `scheme_path`.
+    pub fn spawn(name: &str, scheme_path: &str) -> ServiceWorker {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let scheme_path = scheme_path.to_string();
+        let spawn_res = thread::Builder::new()
+            .name(format!("{}-io", name))
+            .spawn(move || run(scheme_path, receiver));
+        if let Err(e) = spawn_res {
+            // the job sender will report every call as failed
+            warn!("failed to start I/O worker for '{}': {}", name, e);
+        }
+        ServiceWorker { jobs, stuck: Mutex::new(None) }
+    }
+
+    /// Opens the service's scheme, waiting at most `timeout`.
+    /// Returns [None] if the open timed out.
+    pub fn open(&self, timeout: Duration) -> Option<Result<()>> {
+        self.call(Op::Open, timeout).map(|result| result.map(|_| ()))
+    }
+
+    /// Reads from `subscheme` into `buf`, waiting at most `timeout`.
+    /// Returns [None] if the read timed out.
+    pub fn read(&self, subscheme: &str, buf: &mut [u8], timeout: Duration) -> Option<Result<usize>> {
+        let op = Op::Read {
+            subscheme: subscheme.to_string(),
+            len: buf.len(),
+        };
+        self.call(op, timeout).map(|result| {
+            result.map(|(size, data)| {
+                buf.copy_from_slice(&data);
+                size
+            })
+        })
+    }
+
+    /// Writes `data` to `subscheme`, waiting at most `timeout`.
+    /// Returns [None] if the write timed out.
+    pub fn write(&self, subscheme: &str, data: &[u8], timeout: Duration) -> Option<Result<usize>> {
+        let op = Op::Write {
+            subscheme: subscheme.to_string(),
+            data: data.to_vec(),
+        };
+        self.call(op, timeout).map(|result| result.map(|(size, _)| size))
+    }
+
+    /// Runs `probe` against the service's scheme, waiting at most `timeout`.
+    /// Returns [None] if the probe timed out.
+    pub fn probe(&self, probe: &Probe, timeout: Duration) -> Option<Result<()>> {
+        self.call(Op::Probe(probe.clone()), timeout).map(|result| result.map(|_| ()))
+    }
+
+    /// Returns true if the worker is still stuck on a call that timed out.
+    pub fn is_stuck(&self) -> bool {
+        let mut stuck = self.stuck.lock().unwrap();
+        let still_stuck = stuck.as_ref().is_some_and(|receiver| matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+        if !still_stuck {
+            *stuck = None;
+        }
+        still_stuck
+    }
+
+    /// Hands `op` to the worker thread and waits for its result.
+    fn call(&self, op: Op, timeout: Duration) -> Option<Result<(usize, Vec<u8>)>> {
+        if self.is_stuck() {
+            return Some(Err(Error::new(EBUSY)));
+        }
+        let (reply, receiver) = mpsc::channel();
+        if self.jobs.send(Job { op, reply }).is_err() {
+            return Some(Err(Error::new(EBADF)));
+        }
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => Some(result),
+            Err(RecvTimeoutError::Timeout) => {
+                *self.stuck.lock().unwrap() = Some(receiver);
+                None
+            }
+            Err(RecvTimeoutError::Disconnected) => Some(Err(Error::new(EBADF))),
+        }
+    }
+}
+
+/// The worker thread's loop. Runs until the [ServiceWorker] is dropped.
+fn run(scheme_path: String, jobs: Receiver<Job>) {
+    let mut fds = FdCache::new(scheme_path);
+    for job in jobs {
+        let result = match &job.op {
+            Op::Open => fds.get("").map(|_| (0, Vec::new())),
+            Op::Read { subscheme, len } => fds.get(subscheme).and_then(|fd| {
+                let mut data = vec![0; *len];
+                libredox::call::read(fd, &mut data).map(|size| (size, data))
+            }),
+            Op::Write { subscheme, data } => fds
+                .get(subscheme)
+                .and_then(|fd| libredox::call::write(fd, data).map(|size| (size, Vec::new()))),
+            Op::Probe(probe) => run_probe(&fds.scheme_path, probe).map(|size| (size, Vec::new())),
+        };
+        // the service may have been restarted underneath us, so open everything again next time
+        if result.is_err() {
+            fds.close_all();
+        }
+        // the caller may have given up waiting already
+        let _ = job.reply.send(result);
+    }
+    fds.close_all();
+}
+
+/// Opens the path `probe` checks within the scheme at `scheme_path` and does what the probe asks.
+fn run_probe(scheme_path: &str, probe: &Probe) -> Result<usize> {
+    let path = if probe.path.is_empty() {
+        scheme_path.to_string()
+    } else {
+        format!("{}/{}", scheme_path.trim_end_matches('/'), probe.path.trim_start_matches('/'))
+    };
+    let flags = if probe.kind == ProbeKind::Write { O_WRONLY } else { O_RDONLY };
+    let fd = libredox::call::open(path, flags, 0)?;
+    let result = match probe.kind {
+        ProbeKind::Open => Ok(0),
+        ProbeKind::Read => {
+            let mut data = vec![0; probe.len];
+            libredox::call::read(fd, &mut data).and_then(|size| if size < probe.len { Err(Error::new(EIO)) } else { Ok(size) })
+        }
+        ProbeKind::Write => libredox::call::write(fd, probe.payload.as_bytes()),
+        ProbeKind::Fstat => libredox::call::fstat(fd).map(|_| 0),
+    };
+    let _close_res = libredox::call::close(fd);
+    result
+}
+
+/// The file descriptors a worker keeps open on its service's scheme.
+struct FdCache {
+    scheme_path: String,
+    base: Option<usize>,
+    subschemes: HashMap<String, usize>,
+}
+
+impl FdCache {
+    fn new(scheme_path: String) -> FdCache {
+        FdCache {
+            scheme_path,
+            base: None,
+            subschemes: HashMap::new(),
+        }
+    }
+
+    /// Returns the fd of `subscheme`, opening the scheme and duping the subscheme if needed.
+    fn get(&mut self, subscheme: &str) -> Result<usize> {
+        let base = match self.base {
+            Some(fd) => fd,
+            None => {
+                let fd = libredox::call::open(self.scheme_path.clone(), O_RDWR, 0)?;
+                self.base = Some(fd);
+                fd
+            }
+        };
+        if subscheme.is_empty() {
+            return Ok(base);
+        }
+        if let Some(fd) = self.subschemes.get(subscheme) {
+            return Ok(*fd);
+        }
+        let fd = libredox::call::dup(base, subscheme.as_bytes())?;
+        self.subschemes.insert(subscheme.to_string(), fd);
+        Ok(fd)
+    }
+
+    fn close_all(&mut self) {
+        for (_, fd) in self.subschemes.drain() {
+            let _close_res = libredox::call::close(fd);
+        }
+        if let Some(fd) = self.base.take() {
+            let _close_res = libredox::call::close(fd);
+        }
+    }
+}
@@ -24,25 +24,29 @@ use cosmic::widget::table::model::category;
 use cosmic::widget::{table, table::Entity, Container, Text};
 use cosmic::widget::{self, nav_bar};
 use cosmic::{executor, iced};
-use shared::{format_timestamp, format_uptime, get_response, CommandResponse, SMCommand, TOMLMessage};
+use shared::{format_timestamp, format_uptime, get_response, CommandResponse, HealthStatus, SMCommand, ServiceState, TOMLMessage};
 use tracing_subscriber::registry::Data;
 
 #[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Category {
     #[default]
     Name,
+    Type,
     Pid,
     Uptime,
     Msg,
+    State,
 }
 
 impl std::fmt::Display for Category {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
             Self::Name => "Name",
+            Self::Type => "Type",
             Self::Pid => "PID",
             Self::Uptime => "Uptime",
             Self::Msg => "Message",
+            Self::State => "Status",
         })
     }
 }
@@ -51,27 +55,33 @@ impl table::ItemCategory for Category {
     fn width(&self) -> iced::Length {
         match self {
             Self::Name => iced::Length::Fill,
+            Self::Type => iced::Length::Fixed(120.0),
             Self::Pid => iced::Length::Fixed(100.0),
             Self::Uptime => iced::Length::Fixed(250.0),
             Self::Msg => iced::Length::Fixed(250.0),
+            Self::State => iced::Length::Fixed(150.0),
         }
     }
 }
 
 struct Item {
     name: String,
+    service_type: String,
     pid: usize,
     uptime: (i64, i64),
     msg: String,
+    state: ServiceState,
 }
 
 impl Default for Item {
     fn default() -> Self {
         Self {
             name: Default::default(),
+            service_type: Default::default(),
             pid: Default::default(),
             uptime: Default::default(),
             msg: Default::default(),
+            state: Default::default(),
         }
     }
 }
@@ -88,6 +98,7 @@ impl table::ItemInterface<Category> for Item {
     fn get_text(&self, category: Category) -> std::borrow::Cow<'static, str> {
         match category {
             Category::Name => self.name.clone().into(),
+            Category::Type => format!("{:^12}", self.service_type.clone()).into(),
             Category::Pid => {
                 if self.pid == 0 {
                     "".to_string().into()
@@ -106,15 +117,20 @@ impl table::ItemInterface<Category> for Item {
             Category::Msg => {
                 format!("{:^20}", self.msg.clone()).into()
             },
+            Category::State => {
+                format!("{:^14}", self.state.to_string()).into()
+            },
         }
     }
 
     fn compare(&self, other: &Self, category: Category) -> std::cmp::Ordering {
         match category {
             Category::Name => self.name.to_lowercase().cmp(&other.name.to_lowercase()),
+            Category::Type => self.service_type.cmp(&other.service_type),
             Category::Pid => self.pid.cmp(&other.pid),
             Category::Uptime => (self.uptime.1 - self.uptime.0).cmp(&(other.uptime.1 - other.uptime.0)),
             Category::Msg => self.msg.to_lowercase().cmp(&other.msg.to_lowercase()),
+            Category::State => self.state.to_string().cmp(&other.state.to_string()),
         }
     }
 }
@@ -142,6 +158,7 @@ pub enum Message {
     PrintMsg(String),
     Refresh,
     Detail,
+    Events,
     Start(String),
     Stop(String),
     ToPrimary,
@@ -163,6 +180,7 @@ pub struct App {
     selected: Option<String>,
     screen: Screen,
     info_table: bool,
+    events_table: bool,
     //info_table: Option<Container<'static, Message, Theme>>,
 }
 
@@ -196,14 +214,16 @@ impl cosmic::Application for App {
 
         let mut table_model = table::Model::new(vec![
             Category::Name,
+            Category::Type,
             Category::Pid,
             Category::Uptime,
             Category::Msg,
+            Category::State,
         ]);
 
         get_services(&mut table_model);
         let screen: Screen = Screen::Primary;
-        let app = App { core, table_model, selected: None, screen, info_table: false};
+        let app = App { core, table_model, selected: None, screen, info_table: false, events_table: false };
 
         let command = Task::none();
 
@@ -233,12 +253,15 @@ impl cosmic::Application for App {
             Message::Detail => {
                 self.info_table = !self.info_table;
             }
+            Message::Events => {
+                self.events_table = !self.events_table;
+            }
             Message::Start(service_name) => {
                 if let Ok(mut sm_fd) = OpenOptions::new()
                     .write(true)
                     .open("/scheme/service-monitor")
                 {
-                    let cmd = SMCommand::Start { service_name: service_name.clone() }.encode().unwrap();
+                    let cmd = SMCommand::Start { service_name: service_name.clone(), force: false }.encode().unwrap();
                     let _ = sm_fd.write(&cmd);
                     tracing_log::log::info!("Started {}", service_name);
                     get_services(&mut self.table_model);
@@ -250,7 +273,7 @@ impl cosmic::Application for App {
                     .write(true)
                     .open("/scheme/service-monitor")
                 {
-                    let cmd = SMCommand::Stop { service_name: service_name.clone() }.encode().unwrap();
+                    let cmd = SMCommand::Stop { service_name: service_name.clone(), cascade: false }.encode().unwrap();
                     let _ = sm_fd.write(&cmd);
                     tracing_log::log::info!("Stopped {}", service_name);
                 }
@@ -284,6 +307,7 @@ impl cosmic::Application for App {
                 let mut start_msg = Message::NoOp;
                 let mut stop_msg = Message::NoOp;
                 let mut info_tbl: Option<Container<'_, Message, Theme>> = None;
+                let mut selected_name: Option<String> = None;
                 match self.table_model.item(self.table_model.active()) {
                     Some(selected) => {
                         // if some item is selected then start and stop should operate on that
@@ -292,9 +316,12 @@ impl cosmic::Application for App {
                         if self.info_table {
                             info_tbl = get_info(selected.name.clone());
                         }
+                        selected_name = Some(selected.name.clone());
                     },
                     None => {}
                 }
+                // events are shown for the selected service, or for every service if none is selected
+                let events_tbl = if self.events_table { get_events(selected_name) } else { None };
 
                 let button_row = row![
                     cosmic::widget::button::text("Help").on_press(Message::ToDoc),
@@ -302,6 +329,7 @@ impl cosmic::Application for App {
                     cosmic::widget::button::text("Start").on_press(start_msg),
                     cosmic::widget::button::text("Stop").on_press(stop_msg),
                     cosmic::widget::button::text("Info").on_press(Message::Detail),
+                    cosmic::widget::button::text("Events").on_press(Message::Events),
                 ]
                 .spacing(cosmic::theme::spacing().space_s)
                 .align_y(iced::Alignment::Center);
@@ -364,35 +392,24 @@ impl cosmic::Application for App {
                 .height(iced::Length::Shrink)
                 .align_x(iced::Alignment::Center)
                 .align_y(iced::Alignment::Center);
-                let body = match info_tbl {
-                    Some(tbl) => {
-                        cosmic::widget::container(
-                            column![
-                            button_row,
-                                row![
-                                    centered,
-                                    tbl,
-                                ]
-                            ]
-                            .spacing(cosmic::theme::spacing().space_s)
-                            .width(iced::Length::Fill)
-                            .height(iced::Length::Fill)
-                            .align_x(iced::Alignment::Center)
-                        )
-                    }
-                    _ => {
-                        cosmic::widget::container(
-                            column![
-                                button_row,
-                                centered,
-                            ]
-                            .spacing(cosmic::theme::spacing().space_s)
-                            .width(iced::Length::Fill)
-                            .height(iced::Length::Fill)
-                            .align_x(iced::Alignment::Center)
-                        )
-                    }
+                let mut content: Column<'_, Message, Theme, Renderer> = Column::new().push(button_row);
+                content = match info_tbl {
+                    Some(tbl) => content.push(row![
+                        centered,
+                        tbl,
+                    ]),
+                    _ => content.push(centered),
                 };
+                if let Some(tbl) = events_tbl {
+                    content = content.push(tbl);
+                }
+                let body = cosmic::widget::container(
+                    content
+                        .spacing(cosmic::theme::spacing().space_s)
+                        .width(iced::Length::Fill)
+                        .height(iced::Length::Fill)
+                        .align_x(iced::Alignment::Center)
+                );
                 Element::from(body)
             }
 
@@ -417,7 +434,8 @@ impl cosmic::Application for App {
                             cosmic::widget::text("  2. With a service selected, click 'Start' or 'Stop' to start or stop the service.").size(20),
                             cosmic::widget::text("  3. With a service selected, click 'Info' to view detailed statistics on that service.").size(20),
                             cosmic::widget::text("  4. At any time click 'System Log' to view the system log.").size(20),
-                            cosmic::widget::text("  5. Service statistics will be automatically updated every 3 seconds.").size(20),
+                            cosmic::widget::text("  5. Click 'Events' to view recent events of the selected service, or of every service if none is selected.").size(20),
+                            cosmic::widget::text("  6. Service statistics will be automatically updated every 3 seconds.").size(20),
                         ]
                         .spacing(cosmic::theme::spacing().space_s)
                         .width(iced::Length::Fill)
@@ -453,12 +471,14 @@ fn get_services(table_model: &mut table::SingleSelectModel<Item, Category>) {
 
     *table_model = table::Model::new(vec![
         Category::Name,
+        Category::Type,
         Category::Pid,
         Category::Uptime,
         Category::Msg,
+        Category::State,
     ]);
 
-    let list_cmd = SMCommand::List.encode().unwrap();
+    let list_cmd = SMCommand::List { target: None }.encode().unwrap();
 
     let Ok(sm_fd) = &mut OpenOptions::new()
         .write(true)
@@ -482,32 +502,40 @@ fn get_services(table_model: &mut table::SingleSelectModel<Item, Category>) {
                     if !saved_selected.is_empty() && s.name.clone() == saved_selected {
                         let _ = table_model.insert(Item {
                             name: s.name.clone(),
+                            service_type: s.service_type.clone(),
                             pid: s.pid,
                             uptime: (s.time_init, s.time_now),
                             msg: s.message.clone(),
+                            state: s.state,
                         }).activate();
                     } else {
                         let _ = table_model.insert(Item {
                             name: s.name.clone(),
+                            service_type: s.service_type.clone(),
                             pid: s.pid,
                             uptime: (s.time_init, s.time_now),
                             msg: s.message.clone(),
+                            state: s.state,
                         });
                     }
                 } else {
                     if !saved_selected.is_empty() && s.name.clone() == saved_selected {
                         let _ = table_model.insert(Item {
                             name: s.name.clone(),
+                            service_type: s.service_type.clone(),
                             pid: 0,
                             uptime: (0,0),
                             msg: "not running".to_string(),
+                            state: s.state,
                         }).activate();
                     } else {
                         let _ = table_model.insert(Item {
                             name: s.name.clone(),
+                            service_type: s.service_type.clone(),
                             pid: 0,
                             uptime: (0,0),
                             msg: "not running".to_string(),
+                            state: s.state,
                         });
                     }
                 }
@@ -551,14 +579,36 @@ fn get_info(service: String) -> Option<Container<'static, Message, Theme>> {
 
                 let name_text: Vec<String> = ["Name:".to_string(), service.name.clone()].to_vec();
                 column = column.push(get_detail_row(name_text));
+                let state_text: Vec<String> = ["State:".to_string(), service.state.to_string()].to_vec();
+                column = column.push(get_detail_row(state_text));
                 let uptime_text: Vec<String> = ["Uptime:".to_string(), uptime_string.clone()].to_vec();
                 column = column.push(get_detail_row(uptime_text));
                 let time_init_text: Vec<String> = ["Time to init:".to_string(), time_init_string.clone()].to_vec();
                 column = column.push(get_detail_row(time_init_text));
+                if service.time_ready >= service.time_started && service.time_ready != 0 {
+                    let time_ready_text: Vec<String> = ["Time to ready:".to_string(), format_uptime(service.time_started, service.time_ready)].to_vec();
+                    column = column.push(get_detail_row(time_ready_text));
+                }
                 let message_text: Vec<String> = ["Message:".to_string(), service.message.clone()].to_vec();
                 column = column.push(get_detail_row(message_text));
                 let message_time_text: Vec<String> = ["Message time:".to_string(), format_timestamp(service.message_time)].to_vec();
                 column = column.push(get_detail_row(message_time_text));
+                let response_text: Vec<String> = ["Last response:".to_string(), format_timestamp(service.last_response_time)].to_vec();
+                column = column.push(get_detail_row(response_text));
+                let restart_text: Vec<String> = ["Restarts:".to_string(), format!("{}", service.restart_count)].to_vec();
+                column = column.push(get_detail_row(restart_text));
+                if service.needs_restart {
+                    let registry_text: Vec<String> = ["Registry:".to_string(), "Changed, restart needed to apply".to_string()].to_vec();
+                    column = column.push(get_detail_row(registry_text));
+                }
+                if service.health != HealthStatus::Unknown {
+                    let health_text: Vec<String> = ["Health:".to_string(), format!("{} at {}", service.health, format_timestamp(service.health_time))].to_vec();
+                    column = column.push(get_detail_row(health_text));
+                    if !service.health_output.is_empty() {
+                        let health_output_text: Vec<String> = ["Health output:".to_string(), service.health_output.clone()].to_vec();
+                        column = column.push(get_detail_row(health_output_text));
+                    }
+                }
                 let read_text: Vec<String> = ["Live READ count:".to_string(), format!("{}", service.read_count), "total:".to_string(), format!("{}", service.total_reads)].to_vec();
                 column = column.push(get_detail_row(read_text));
                 let write_text: Vec<String> = ["Live WRITE count:".to_string(), format!("{}", service.write_count), "total:".to_string(), format!("{}", service.total_writes)].to_vec();
@@ -574,11 +624,23 @@ fn get_info(service: String) -> Option<Container<'static, Message, Theme>> {
             } else {
                 let name_text: Vec<String> = ["Name:".to_string(), service.name.clone()].to_vec();
                 column = column.push(get_detail_row(name_text));
+                let state_text: Vec<String> = ["State:".to_string(), service.state.to_string()].to_vec();
+                column = column.push(get_detail_row(state_text));
 
                 let message_text: Vec<String> = ["Message:".to_string(), service.message.clone()].to_vec();
                 column = column.push(get_detail_row(message_text));
                 let message_time_text: Vec<String> = ["Message time:".to_string(), format_timestamp(service.message_time)].to_vec();
                 column = column.push(get_detail_row(message_time_text));
+                let restart_text: Vec<String> = ["Restarts:".to_string(), format!("{}", service.restart_count)].to_vec();
+                column = column.push(get_detail_row(restart_text));
+                if !service.last_exit.is_empty() {
+                    let exit_text: Vec<String> = ["Last exit:".to_string(), format!("{} at {}", service.last_exit, format_timestamp(service.last_exit_time))].to_vec();
+                    column = column.push(get_detail_row(exit_text));
+                }
+                if service.next_retry_time != 0 {
+                    let retry_text: Vec<String> = ["Next restart:".to_string(), format_timestamp(service.next_retry_time)].to_vec();
+                    column = column.push(get_detail_row(retry_text));
+                }
                 let read_text: Vec<String> = ["Total READ count:".to_string(), format!("{}", service.total_reads)].to_vec();
                 column = column.push(get_detail_row(read_text));
                 let write_text: Vec<String> = ["Total WRITE count:".to_string(), format!("{}", service.total_writes)].to_vec();
@@ -615,6 +677,61 @@ fn get_info(service: String) -> Option<Container<'static, Message, Theme>> {
     }
 }
 
+/// Number of recent events shown in the events panel.
+const EVENTS_SHOWN: usize = 20;
+
+fn get_events(service: Option<String>) -> Option<Container<'static, Message, Theme>> {
+    let events_cmd = SMCommand::Events { service_name: service, limit: EVENTS_SHOWN }.encode().unwrap();
+
+    let Ok(sm_fd) = &mut OpenOptions::new()
+        .write(true)
+        .open("/scheme/service-monitor")
+    else {
+        panic!()
+    };
+    let _ = File::write(sm_fd, &events_cmd);
+
+    let response_buffer = get_response(sm_fd);
+    let response_string = std::str::from_utf8(&response_buffer)
+        .expect("Error parsing response to UTF8")
+        .to_string();
+    let response: CommandResponse = toml::from_str(&response_string)
+        .expect("Error parsing CommandResponse!");
+
+    match response.message {
+        Some(TOMLMessage::Events(events)) => {
+            let mut column: Column<'static, Message, Theme, Renderer> = Column::new();
+            // newest first, so the latest events stay at the top of the panel
+            for event in events.iter().rev() {
+                let event_text: Vec<String> = [
+                    format_timestamp(event.time),
+                    format!("{}: {} ({} -> {}) {}", event.service, event.kind, event.old_state, event.new_state, event.reason),
+                ].to_vec();
+                column = column.push(get_detail_row(event_text));
+            }
+            Some(
+                cosmic::widget::container(
+                    column,
+                )
+                .style(|_theme| {
+                    //TODO: theme this color
+                    widget::container::Style {
+                        background: Some(Background::Color(Color::from_rgba8(
+                            0x40, 0x40, 0x40, 0.5
+                        ))),
+                        ..Default::default()
+                    }
+                })
+                .width(iced::Length::Fill)
+                .height(iced::Length::Shrink)
+
+                .into()
+            )
+        }
+        _ => {None}
+    }
+}
+
 fn get_detail_row(strings: Vec<String>) -> Row<'static, Message, Theme, Renderer>{
     let mut row: Row<'static, Message, Theme, Renderer> = Row::new();
     // let static_strings: Vec<String> = strings.clone();
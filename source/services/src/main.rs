@@ -1,6 +1,6 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use serde::de;
-use shared::{format_timestamp, format_uptime, get_response, CommandResponse, SMCommand, TOMLMessage};
+use shared::{format_timestamp, format_uptime, get_response, CommandResponse, HealthStatus, SMCommand, TOMLMessage, WatchHandle};
 use std::{
     fmt::format, fs::{File, OpenOptions}, io::{Read, Write}
 };
@@ -12,11 +12,27 @@ use comfy_table;
 #[command(version, about, long_about = None, disable_help_subcommand = true)]
 struct Cli {
     #[command(subcommand)]
-    cmd: SMCommand,
+    cmd: CliCommand,
+}
+
+/// The commands of the CLI, which are the service monitor's commands plus the ones handled by the CLI itself.
+#[derive(Subcommand)]
+enum CliCommand {
+    #[command(about = "Print service events as they happen, until interrupted")]
+    Watch {
+        #[arg(help = "If present, only events for this service are printed")]
+        service_name: Option<String>,
+    },
+    #[command(flatten)]
+    Monitor(SMCommand),
 }
 
 fn main() {
     let cli = Cli::parse();
+    let cmd = match cli.cmd {
+        CliCommand::Watch { service_name } => return watch(service_name),
+        CliCommand::Monitor(cmd) => cmd,
+    };
 
     let Ok(sm_fd) = &mut OpenOptions::new()
         .write(true)
@@ -25,8 +41,7 @@ fn main() {
         panic!()
     };
 
-    let cmd_bytes = &cli
-        .cmd
+    let cmd_bytes = &cmd
         .encode()
         .expect("Failed to encode command to byte buffer");
 
@@ -44,7 +59,7 @@ fn main() {
                 println!("{str}");
             }
             Some(TOMLMessage::ServiceStats(stats)) => {
-                let header_names = vec!["Name", "PID", "Uptime", "Message", "Status"];
+                let header_names = vec!["Name", "Type", "PID", "Uptime", "Message", "Status"];
 
                 let mut table_fmt = comfy_table::Table::new();
                 let mut headers = Vec::<comfy_table::Cell>::new();
@@ -55,10 +70,11 @@ fn main() {
                 for k in stats {
                     let mut row: Vec<String> = Vec::new();
                     row.push(k.name.clone());
-                    row.push(if k.running {k.pid.to_string()} else {String::from("None")});
+                    row.push(k.service_type.clone());
+                    row.push(if !k.running {String::from("None")} else if k.pid == 0 {String::from("Unknown")} else {k.pid.to_string()});
                     row.push(if k.running {format_uptime(k.time_init, k.time_now)} else {String::from("None")});
                     row.push(if k.running {k.message.clone()} else {String::from("None")});
-                    row.push(if k.running {String::from("Running")} else {String::from("Not running")});
+                    row.push(if k.needs_restart {format!("{} (restart needed)", k.state)} else {k.state.to_string()});
                     rows.push(row);
                 }
 
@@ -82,10 +98,15 @@ fn main() {
                 let mut rows2: Vec<Vec<String>> = Vec::new();
 
                 let mut service_row: Vec<String> = Vec::new();
+                let mut state_row: Vec<String> = Vec::new();
                 let mut uptime_row: Vec<String> = Vec::new();
                 let mut init_row: Vec<String> = Vec::new();
                 let mut message_row: Vec<String> = Vec::new();
                 let mut message_time_row: Vec<String> = Vec::new();
+                let mut response_row: Vec<String> = Vec::new();
+                let mut restart_row: Vec<String> = Vec::new();
+                let mut retry_row: Vec<String> = Vec::new();
+                let mut exit_row: Vec<String> = Vec::new();
                 let mut read_row: Vec<String> = Vec::new();
                 let mut write_row: Vec<String> = Vec::new();
                 let mut open_row: Vec<String> = Vec::new();
@@ -94,6 +115,8 @@ fn main() {
                 if detail.running {
                     service_row.push("Service:".to_string());
                     service_row.push(detail.name.clone());
+                    state_row.push("State:".to_string());
+                    state_row.push(detail.state.to_string());
                     uptime_row.push("Uptime:".to_string());
                     uptime_row.push(format_uptime(detail.time_init, detail.time_now));
                     init_row.push("Time to init:".to_string());
@@ -102,12 +125,31 @@ fn main() {
                     message_row.push(detail.message.clone());
                     message_time_row.push("Message time:".to_string()); 
                     message_time_row.push(format_timestamp(detail.message_time));
+                    response_row.push("Last response:".to_string());
+                    response_row.push(format_timestamp(detail.last_response_time));
 
                     rows1.push(service_row);
+                    rows1.push(state_row);
                     rows1.push(uptime_row);
                     rows1.push(init_row);
+                    if detail.time_ready >= detail.time_started && detail.time_ready != 0 {
+                        rows1.push(vec!["Time to ready:".to_string(), format_uptime(detail.time_started, detail.time_ready)]);
+                    }
                     rows1.push(message_row);
                     rows1.push(message_time_row);
+                    rows1.push(response_row);
+                    restart_row.push("Restarts:".to_string());
+                    restart_row.push(format!("{}", detail.restart_count));
+                    rows1.push(restart_row);
+                    if detail.needs_restart {
+                        rows1.push(vec!["Registry:".to_string(), "Changed, restart needed to apply".to_string()]);
+                    }
+                    if detail.health != HealthStatus::Unknown {
+                        rows1.push(vec!["Health:".to_string(), format!("{} at {}", detail.health, format_timestamp(detail.health_time))]);
+                        if !detail.health_output.is_empty() {
+                            rows1.push(vec!["Health output:".to_string(), detail.health_output.clone()]);
+                        }
+                    }
               
                     read_row.push("Live READ count:".to_string());
                     read_row.push(format!("{}", detail.read_count));
@@ -150,11 +192,27 @@ fn main() {
                 } else {
                     service_row.push("Service:".to_string());
                     service_row.push(detail.name.clone());
+                    state_row.push("State:".to_string());
+                    state_row.push(detail.state.to_string());
                     message_row.push("Message:".to_string());
                     message_row.push(detail.message.clone());
 
                     rows1.push(service_row);
+                    rows1.push(state_row);
                     rows1.push(message_row);
+                    restart_row.push("Restarts:".to_string());
+                    restart_row.push(format!("{}", detail.restart_count));
+                    rows1.push(restart_row);
+                    if !detail.last_exit.is_empty() {
+                        exit_row.push("Last exit:".to_string());
+                        exit_row.push(format!("{} at {}", detail.last_exit, format_timestamp(detail.last_exit_time)));
+                        rows1.push(exit_row);
+                    }
+                    if detail.next_retry_time != 0 {
+                        retry_row.push("Next restart:".to_string());
+                        retry_row.push(format_timestamp(detail.next_retry_time));
+                        rows1.push(retry_row);
+                    }
 
                 
                     read_row.push("Total READ count:".to_string());
@@ -181,6 +239,52 @@ fn main() {
                 }
 
             }
+            Some(TOMLMessage::RegistryDiff(diff)) => {
+                if diff.is_empty() {
+                    println!("Registry unchanged");
+                }
+                for (title, marker, entries) in [("Added", "+", &diff.added), ("Removed", "-", &diff.removed), ("Changed", "~", &diff.changed)] {
+                    if entries.is_empty() {
+                        continue;
+                    }
+                    println!("{title}:");
+                    for entry in entries {
+                        if entry.fields.is_empty() {
+                            println!("  {} {}: {}", marker, entry.name, entry.action);
+                        } else {
+                            println!("  {} {} ({}): {}", marker, entry.name, entry.fields.join(", "), entry.action);
+                        }
+                    }
+                }
+            }
+            Some(TOMLMessage::Events(events)) => {
+                let header_names = vec!["Time", "Service", "Event", "From", "To", "Reason"];
+
+                let mut table_fmt = comfy_table::Table::new();
+                let mut headers = Vec::<comfy_table::Cell>::new();
+                let mut rows: Vec<Vec<String>> = Vec::new();
+                for h in header_names {
+                    headers.push(comfy_table::Cell::new(&h).add_attribute(comfy_table::Attribute::Reverse));
+                }
+                for event in events {
+                    let mut row: Vec<String> = Vec::new();
+                    row.push(format_timestamp(event.time));
+                    row.push(event.service.clone());
+                    row.push(event.kind.to_string());
+                    row.push(event.old_state.to_string());
+                    row.push(event.new_state.to_string());
+                    row.push(event.reason.clone());
+                    rows.push(row);
+                }
+
+                table_fmt.load_preset(comfy_table::presets::NOTHING)
+                    .set_content_arrangement(comfy_table::ContentArrangement::Dynamic)
+                    .set_header(headers)
+                    .add_rows(rows)
+                    ;
+
+                println!("{table_fmt}");
+            }
             _ => {
                 if response.status.success {
                     println!("Command '{}' succeeded", response.status.command);
@@ -192,3 +296,29 @@ fn main() {
         }
     }
 }
+
+/// Prints each service event as the service monitor reports it.
+fn watch(service_name: Option<String>) {
+    let mut handle = WatchHandle::open().expect("Failed to watch the service monitor");
+    loop {
+        let events = handle.next_events().expect("Failed to read events from the service monitor");
+        for event in events {
+            if service_name.as_ref().is_some_and(|name| *name != event.service) {
+                continue;
+            }
+            if event.old_state == event.new_state {
+                println!("{} {}: {} ({})", format_timestamp(event.time), event.service, event.kind, event.reason);
+            } else {
+                println!(
+                    "{} {}: {} {} -> {} ({})",
+                    format_timestamp(event.time),
+                    event.service,
+                    event.kind,
+                    event.old_state,
+                    event.new_state,
+                    event.reason
+                );
+            }
+        }
+    }
+}
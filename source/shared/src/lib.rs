@@ -1,6 +1,8 @@
 //! Crate containing structs and functions shared by `service-monitor` and its front-ends
 
 use clap::Subcommand;
+use libredox::{flag::{O_NONBLOCK, O_RDONLY, O_RDWR}, Fd};
+use syscall::{data::Event, EVENT_READ};
 use std::{fs::File, io::Read, str};
 use serde::{Deserialize, Serialize};
 use chrono::{self, DateTime, Local, NaiveDateTime, TimeZone};
@@ -9,18 +11,39 @@ use chrono::{self, DateTime, Local, NaiveDateTime, TimeZone};
 #[derive(Subcommand, Serialize, Deserialize, Clone)]
 #[serde(tag = "command")]
 pub enum SMCommand {
-    #[command(about = "Start a service")]
+    #[command(about = "Start a service, or every service in a target")]
     Start {
-        #[arg(help = "The name of the service")]
+        #[arg(help = "The name of the service, or @target to start a target's services and their dependencies")]
         service_name: String,
+
+        #[arg(short, long, help = "If present, any dependencies that are not running are started first")]
+        #[serde(default)]
+        force: bool,
     },
-    #[command(about = "Stop a service")]
+    #[command(about = "Stop a service, or every service in a target")]
     Stop {
+        #[arg(help = "The name of the service, or @target to stop a target's services and the dependencies nothing else needs")]
+        service_name: String,
+
+        #[arg(short, long, help = "If present, running services that depend on this service are stopped first")]
+        #[serde(default)]
+        cascade: bool,
+    },
+    #[command(about = "Stop a service gracefully and start it again with the same configuration")]
+    Restart {
         #[arg(help = "The name of the service")]
         service_name: String,
+
+        #[arg(long, help = "If present, the state the service exported before stopping is handed back to it once it has started")]
+        #[serde(default)]
+        restore: bool,
     },
     #[command(about = "List all services and their respective statuses")]
-    List,
+    List {
+        #[arg(help = "If present, only the services in this @target and their dependencies are listed")]
+        #[serde(default)]
+        target: Option<String>,
+    },
     #[command(about = "Clear short-term stats for a service")]
     Clear {
         #[arg(help = "The name of the service")]
@@ -31,6 +54,17 @@ pub enum SMCommand {
         #[arg(help = "The name of the service")]
         service_name: String,
     },
+    #[command(about = "Re-read the registry and apply any changes to it")]
+    Reload,
+    #[command(about = "Show recent service lifecycle events, oldest first")]
+    Events {
+        #[arg(help = "If present, only events for this service are shown")]
+        service_name: Option<String>,
+
+        #[arg(short, long, default_value_t = 20, help = "The number of most recent events to show, or 0 for all of them")]
+        #[serde(default)]
+        limit: usize,
+    },
     #[command(about = "Change and view the registry. Try 'services registry --help' for more information")]
     Registry {
         #[command(subcommand)]
@@ -41,11 +75,14 @@ pub enum SMCommand {
 impl std::fmt::Display for SMCommand {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            SMCommand::Start { service_name: _ } => write!(f, ""),
-            SMCommand::Stop { service_name: _ } => write!(f, ""),
-            SMCommand::List => write!(f, "list"),
+            SMCommand::Start { service_name: _, force: _ } => write!(f, ""),
+            SMCommand::Stop { service_name: _, cascade: _ } => write!(f, ""),
+            SMCommand::Restart { service_name: _, restore: _ } => write!(f, "restart"),
+            SMCommand::List { target: _ } => write!(f, "list"),
             SMCommand::Clear { service_name: _ } => write!(f, "clear"),
             SMCommand::Info { service_name: _ } => write!(f, "info"),
+            SMCommand::Reload => write!(f, "reload"),
+            SMCommand::Events { service_name: _, limit: _ } => write!(f, "events"),
             SMCommand::Registry { subcommand } => write!(f, "registry {}", subcommand),
         }
     }
@@ -206,6 +243,84 @@ pub struct CommandStatus {
     pub success: bool,
 }
 
+/// The lifecycle state of a service, as tracked by the service monitor.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ServiceState {
+    /// The service has been launched and the service monitor is waiting for it to become ready.
+    Starting,
+    /// The service is alive and responding.
+    Running,
+    /// The service is alive after being restarted by the service monitor. Clearing its stats moves it back to [ServiceState::Running].
+    Restarted,
+    /// The service monitor is in the process of stopping the service.
+    Stopping,
+    /// The service is not running and will not be started until it is requested.
+    #[default]
+    Stopped,
+    /// The service failed and is not running. It may be waiting for a scheduled restart.
+    Failed,
+    /// The service failed too often and the service monitor gave up restarting it.
+    Unresponsive,
+}
+
+impl ServiceState {
+    /// Returns true if the service is fully up and serving in this state. A service that is starting or
+    /// stopping may have a live process, but is not counted as running.
+    pub fn is_running(&self) -> bool {
+        matches!(self, ServiceState::Running | ServiceState::Restarted)
+    }
+}
+
+impl std::fmt::Display for ServiceState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceState::Starting => write!(f, "STARTING"),
+            ServiceState::Running => write!(f, "RUNNING"),
+            ServiceState::Restarted => write!(f, "RESTARTED"),
+            ServiceState::Stopping => write!(f, "STOPPING"),
+            ServiceState::Stopped => write!(f, "STOPPED"),
+            ServiceState::Failed => write!(f, "FAILED"),
+            ServiceState::Unresponsive => write!(f, "UNRESPONSIVE"),
+        }
+    }
+}
+
+/// How healthy a service's `health_check` command last judged it to be, from the command's exit code.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum HealthStatus {
+    /// The command has not finished since the service was started, or the service has no `health_check`.
+    #[default]
+    Unknown,
+    /// The command exited with code 0.
+    Healthy,
+    /// The command exited with code 1.
+    Degraded,
+    /// The command exited with any other code, was killed, timed out, or could not be run.
+    Unhealthy,
+}
+
+impl HealthStatus {
+    /// Interprets the exit code of a health check command, which is [None] if it was killed by a signal.
+    pub fn from_exit_code(code: Option<i32>) -> HealthStatus {
+        match code {
+            Some(0) => HealthStatus::Healthy,
+            Some(1) => HealthStatus::Degraded,
+            _ => HealthStatus::Unhealthy,
+        }
+    }
+}
+
+impl std::fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HealthStatus::Unknown => write!(f, "UNKNOWN"),
+            HealthStatus::Healthy => write!(f, "HEALTHY"),
+            HealthStatus::Degraded => write!(f, "DEGRADED"),
+            HealthStatus::Unhealthy => write!(f, "UNHEALTHY"),
+        }
+    }
+}
+
 /// Struct containing data about a registered service's runtime stats.
 /// This is used primarily for the `services list` command.
 #[derive(Serialize, Deserialize)]
@@ -217,7 +332,12 @@ pub struct ServiceRuntimeStats {
     pub time_now: i64,
     pub message: String,
     pub running: bool,
-
+    /// The service's lifecycle state.
+    pub state: ServiceState,
+    /// The service's type from the registry, e.g. "daemon" or "unmanaged".
+    pub service_type: String,
+    /// True if the service's registry entry changed while it was running, so it must be restarted to use it.
+    pub needs_restart: bool,
 }
 
 /// Struct containing detailed data about a registered service's runtime stats.
@@ -228,6 +348,9 @@ pub struct ServiceDetailStats {
     pub pid: usize,
     pub time_init: i64,
     pub time_started: i64,
+    /// The timestamp, in milliseconds from the Unix epoch, that the service became ready after it was last started,
+    /// or 0 if it has not since the service monitor started.
+    pub time_ready: i64,
     pub time_now: i64,
     pub read_count: u64,
     pub write_count: u64,
@@ -243,9 +366,191 @@ pub struct ServiceDetailStats {
     pub total_errors: u64,
     pub message: String,
     pub message_time: i64,
+    /// The timestamp, in milliseconds from the Unix epoch, that the service last responded to the service monitor.
+    pub last_response_time: i64,
+    /// The number of times the service monitor has restarted the service after a failure.
+    pub restart_count: u32,
+    /// The timestamp, in milliseconds from the Unix epoch, of the next scheduled restart, or 0 if none is scheduled.
+    pub next_retry_time: i64,
+    /// How the service's process last exited (e.g. "exited with code 1"), or empty if it has not been seen exiting.
+    pub last_exit: String,
+    /// The timestamp, in milliseconds from the Unix epoch, that the service's process last exited.
+    pub last_exit_time: i64,
     pub running: bool,
+    /// The service's lifecycle state.
+    pub state: ServiceState,
+    /// True if the service's registry entry changed while it was running, so it must be restarted to use it.
+    pub needs_restart: bool,
+    /// The result of the service's `health_check` command the last time it finished.
+    pub health: HealthStatus,
+    /// The start of what the `health_check` command printed the last time it finished.
+    pub health_output: String,
+    /// The timestamp, in milliseconds from the Unix epoch, that the `health_check` command last finished.
+    pub health_time: i64,
+}
+
+/// The differences between the registry on disk and the services in the service monitor, found by a reload.
+/// This is used primarily for the `services reload` command.
+#[derive(Serialize, Deserialize, Default)]
+pub struct RegistryDiff {
+    /// Services that are new in the registry.
+    pub added: Vec<DiffEntry>,
+    /// Services that are no longer in the registry.
+    pub removed: Vec<DiffEntry>,
+    /// Services whose registry entry changed.
+    pub changed: Vec<DiffEntry>,
+}
+
+impl RegistryDiff {
+    /// Returns true if the registry had no differences.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
 }
 
+/// One service in a [RegistryDiff].
+#[derive(Serialize, Deserialize)]
+pub struct DiffEntry {
+    pub name: String,
+    /// The registry fields that changed. Empty for services that were added or removed.
+    pub fields: Vec<String>,
+    /// What the service monitor did about the difference.
+    pub action: String,
+}
+
+
+/// What happened to a service in a [ServiceEvent].
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EventKind {
+    /// The service was started.
+    Start,
+    /// The service was stopped.
+    Stop,
+    /// The service's process was seen exiting.
+    Exit,
+    /// The service monitor restarted the service, or gave up restarting it.
+    Restart,
+    /// The service monitor handled a failure of the service.
+    Recover,
+    /// A read or write on the service's scheme timed out.
+    Timeout,
+    /// A periodic health check on the service failed or succeeded again.
+    Health,
+    /// The service was found still running from before the service monitor restarted.
+    Adopt,
+    /// The service's short-term stats were cleared.
+    Clear,
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::Start => write!(f, "start"),
+            EventKind::Stop => write!(f, "stop"),
+            EventKind::Exit => write!(f, "exit"),
+            EventKind::Restart => write!(f, "restart"),
+            EventKind::Recover => write!(f, "recover"),
+            EventKind::Timeout => write!(f, "timeout"),
+            EventKind::Health => write!(f, "health"),
+            EventKind::Adopt => write!(f, "adopt"),
+            EventKind::Clear => write!(f, "clear"),
+        }
+    }
+}
+
+/// Something that happened to a service, as recorded in the service monitor's event journal.
+/// This is used primarily for the `services events` command.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServiceEvent {
+    /// The timestamp, in milliseconds from the Unix epoch, of the event.
+    pub time: i64,
+    pub service: String,
+    pub kind: EventKind,
+    /// The service's state before the event.
+    pub old_state: ServiceState,
+    /// The service's state after the event, which is the same as `old_state` if the event did not change it.
+    pub new_state: ServiceState,
+    pub reason: String,
+}
+
+/// Ends each event in the stream read from a watch handle. TOML text never contains a NUL byte.
+pub const FRAME_END: u8 = 0;
+
+impl ServiceEvent {
+    /// Encodes this event as it is sent to watch handles, a TOML string ended by a NUL byte.
+    pub fn encode_frame(&self) -> Result<Vec<u8>, String> {
+        toml::to_string(self)
+            .map(|s| {
+                let mut frame = s.into_bytes();
+                frame.push(FRAME_END);
+                frame
+            })
+            .map_err(|e| format!("Failed to encode ServiceEvent into string: {}", e))
+    }
+
+    /// Decodes every complete event at the start of `stream`, removing them from it.
+    /// An incomplete event at the end of `stream` is left there until the rest of it is read.
+    pub fn decode_frames(stream: &mut Vec<u8>) -> Result<Vec<ServiceEvent>, String> {
+        let mut events = Vec::new();
+        while let Some(end) = stream.iter().position(|byte| *byte == FRAME_END) {
+            let frame: Vec<u8> = stream.drain(..=end).collect();
+            let toml_str = str::from_utf8(&frame[..end])
+                .map_err(|e| format!("Failed to decode bytes into string: {}", e))?;
+            let event = toml::from_str(toml_str)
+                .map_err(|e| format!("Failed to decode bytes into ServiceEvent: {}", e))?;
+            events.push(event);
+        }
+        Ok(events)
+    }
+}
+
+/// A watch handle on the service monitor, which streams every [ServiceEvent] as it happens.
+///
+/// The service monitor never blocks on a read, so the handle is registered with an event queue and
+/// [WatchHandle::next_events] waits for the handle to become readable.
+pub struct WatchHandle {
+    fd: Fd,
+    event_queue: Fd,
+    stream: Vec<u8>,
+}
+
+impl WatchHandle {
+    /// Opens a watch handle on the service monitor's scheme.
+    pub fn open() -> Result<WatchHandle, String> {
+        let fd = Fd::open("/scheme/service-monitor/watch", O_RDONLY | O_NONBLOCK, 0)
+            .map_err(|e| format!("Failed to open a watch handle on the service monitor: {}", e))?;
+        let event_queue = Fd::open("/scheme/event", O_RDWR, 0)
+            .map_err(|e| format!("Failed to open an event queue: {}", e))?;
+        let event = Event { id: fd.raw(), flags: EVENT_READ, data: 0 };
+        event_queue
+            .write(&event)
+            .map_err(|e| format!("Failed to wait on the watch handle: {}", e))?;
+        Ok(WatchHandle { fd, event_queue, stream: Vec::new() })
+    }
+
+    /// Waits until at least one event is available and returns every event that is.
+    pub fn next_events(&mut self) -> Result<Vec<ServiceEvent>, String> {
+        loop {
+            let mut buf = [0u8; 1024];
+            loop {
+                match self.fd.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(size) => self.stream.extend_from_slice(&buf[..size]),
+                    Err(e) if e.is_wouldblock() => break,
+                    Err(e) => return Err(format!("Failed to read from the watch handle: {}", e)),
+                }
+            }
+            let events = ServiceEvent::decode_frames(&mut self.stream)?;
+            if !events.is_empty() {
+                return Ok(events);
+            }
+            let mut event = Event::default();
+            self.event_queue
+                .read(&mut event)
+                .map_err(|e| format!("Failed to wait on the watch handle: {}", e))?;
+        }
+    }
+}
 
 /// Enum defining types of messages we may expect to get from a [CommandResponse].
 #[derive(Serialize, Deserialize)]
@@ -253,6 +558,8 @@ pub enum TOMLMessage {
     String(String),
     ServiceStats(Vec<ServiceRuntimeStats>),
     ServiceDetail(ServiceDetailStats),
+    Events(Vec<ServiceEvent>),
+    RegistryDiff(RegistryDiff),
 }
 
 /// Reads the command responsed buffer from the service-monitor's scheme.
@@ -306,4 +613,56 @@ pub fn format_timestamp(timestamp_ms: i64) -> String {
     }
     let timestamp = dt_opt.unwrap();
     timestamp.to_string()
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(reason: &str) -> ServiceEvent {
+        ServiceEvent {
+            time: 1,
+            service: "a".to_string(),
+            kind: EventKind::Start,
+            old_state: ServiceState::Stopped,
+            new_state: ServiceState::Running,
+            reason: reason.to_string(),
+        }
+    }
+
+    #[test]
+    fn partial_frame_is_left_in_the_stream() {
+        let first = event("first").encode_frame().unwrap();
+        let second = event("second").encode_frame().unwrap();
+        let mut stream = first.clone();
+        stream.extend_from_slice(&second[..second.len() / 2]);
+
+        let events = ServiceEvent::decode_frames(&mut stream).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason, "first");
+        assert_eq!(stream, second[..second.len() / 2]);
+
+        stream.extend_from_slice(&second[second.len() / 2..]);
+        let events = ServiceEvent::decode_frames(&mut stream).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason, "second");
+        assert!(stream.is_empty());
+    }
+
+    #[test]
+    fn stream_without_a_whole_frame_decodes_nothing() {
+        let frame = event("first").encode_frame().unwrap();
+        let mut stream = frame[..frame.len() - 1].to_vec();
+        assert!(ServiceEvent::decode_frames(&mut stream).unwrap().is_empty());
+        assert_eq!(stream, frame[..frame.len() - 1]);
+    }
+
+    #[test]
+    fn health_follows_the_check_exit_code() {
+        assert_eq!(HealthStatus::from_exit_code(Some(0)), HealthStatus::Healthy);
+        assert_eq!(HealthStatus::from_exit_code(Some(1)), HealthStatus::Degraded);
+        assert_eq!(HealthStatus::from_exit_code(Some(2)), HealthStatus::Unhealthy);
+        assert_eq!(HealthStatus::from_exit_code(Some(-1)), HealthStatus::Unhealthy);
+        // killed by a signal
+        assert_eq!(HealthStatus::from_exit_code(None), HealthStatus::Unhealthy);
+    }
+}